@@ -41,7 +41,9 @@ async fn main(_spawner: Spawner) -> ! {
 
     adc_blocking_test();
         
-    adc_dma_test();
+    adc_dma_test().await;
+
+    dma_mem_test().await;
 
     uart_test();
 
@@ -129,11 +131,11 @@ fn adc_blocking_test() {
 }
 
 /// Tests the ADC interface in DMA mode.
-/// This means that the function will return immediately and the ADC conversion
-/// will be done in the background.
-fn adc_dma_test() {
+/// The conversions are done in the background, the test waits for the DMA
+/// to fill the buffer instead of sleeping.
+async fn adc_dma_test() {
     let dma_config = dma::Config::new_peri_to_mem();
-    let mut dma_channel = dma::DmaChannel::new(dma_config, 1, 0).unwrap();
+    let mut dma_channel = dma::DmaChannel::new(dma_config, dma::DmaChannelId::Ch1, dma::DmaRequest::Adc).unwrap();
 
     let mut adc_config = adc::AdcConfig::new();
     adc_config.set_as_dma();
@@ -143,18 +145,27 @@ fn adc_dma_test() {
     unsafe {
         adc.start_dma(&mut ADC_DATA).unwrap();
     }
-    
-    unsafe { csdk::HAL_Delay(100); }
+
+    dma_channel.wait().await.unwrap();
     unsafe{
         defmt::println!("adc dma value  {}", ADC_DATA);
     }
 
-    unsafe { csdk::HAL_Delay(100); }
-    // defmt::println!("adc dma value  {}", ADC_DATA);
-
     adc.stop_dma().unwrap();
 }
 
+/// Tests memory-to-memory DMA by filling and copying a buffer.
+async fn dma_mem_test() {
+    let mut dma_channel = dma::DmaChannel::new_mem_to_mem(dma::DmaChannelId::Ch2).unwrap();
+
+    let mut src = [0u32; 16];
+    dma::blocking_fill(&mut dma_channel, &0x5a5a_5a5a, &mut src).unwrap();
+
+    let mut dst = [0u32; 16];
+    dma::copy(&mut dma_channel, &src, &mut dst).await.unwrap();
+    defmt::println!("dma copy  {}", dst == src);
+}
+
 /// Tests the UART interface by writing the string "a" to the serial port.
 fn uart_test() {
     let mut scl = gpio::AnyPin::new_from_csdk(csdk::GPIOA, csdk::GPIO_PIN_3).unwrap();
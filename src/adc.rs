@@ -215,6 +215,7 @@ impl Adc {
 
 impl dma::HasDmaField for Adc {
     fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel){
+        dma_handle.set_request(dma::DmaRequest::Adc);
         self.handle.DMA_Handle = &mut dma_handle.handle;
     }
     
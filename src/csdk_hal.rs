@@ -28,4 +28,15 @@ where
         csdk::HAL_StatusTypeDef_HAL_TIMEOUT => Err(crate::Error::Timeout),
         _ => panic!(),
     }
+}
+
+/// Busy-wait for about `us` microseconds, derived from the current system clock.
+///
+/// Only meant for short bit-level timings, use `HAL_Delay` or `embassy_time` for anything longer.
+pub fn delay_us(us: u32) {
+    // one iteration is about 4 cycles on the Cortex-M0+
+    let iterations = crate::rcc::get_sys_clock_freq() / 1_000_000 * us / 4;
+    for _ in 0..iterations {
+        core::hint::spin_loop();
+    }
 }
\ No newline at end of file
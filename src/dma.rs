@@ -1,14 +1,59 @@
 use core::ffi::c_void;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::{Context, Poll};
 
 use defmt::bitflags;
+use embassy_sync::waitqueue::AtomicWaker;
 
 use crate::*;
 use csdk_hal::check;
 use crate::csdk::interrupts::interrupt;
 
-const DMA_CHANNEL_COUNT: usize = 16;
+const DMA_CHANNEL_COUNT: usize = 3;
 static mut DMA_CHANNELS: [Option<*mut csdk::DMA_HandleTypeDef>; DMA_CHANNEL_COUNT] = [None; DMA_CHANNEL_COUNT];
 
+/// Per-channel interrupt state for [`Transfer`].
+struct State {
+    waker: AtomicWaker,
+    /// Interrupt flags (`EVENT_*`) seen since the last [`State::take_events`].
+    events: AtomicU8,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            events: AtomicU8::new(0),
+        }
+    }
+
+    // thumbv6m has no atomic read-modify-write, these run in a critical section.
+
+    fn add_events(&self, events: u8) {
+        critical_section::with(|_| {
+            self.events.store(self.events.load(Ordering::Relaxed) | events, Ordering::Relaxed);
+        });
+    }
+
+    fn take_events(&self) -> u8 {
+        critical_section::with(|_| {
+            let events = self.events.load(Ordering::Relaxed);
+            self.events.store(0, Ordering::Relaxed);
+            events
+        })
+    }
+}
+
+static STATES: [State; DMA_CHANNEL_COUNT] = [State::new(), State::new(), State::new()];
+
+// Flags of one channel in ISR, the same bits enable their interrupts in CCR.
+const EVENT_GLOBAL: u32 = 1 << 0;
+const EVENT_COMPLETE: u8 = 1 << 1;
+const EVENT_HALF: u8 = 1 << 2;
+const EVENT_ERROR: u8 = 1 << 3;
+
 bitflags! {
     pub struct DmaErrorFlags: u32 {
         const TRANSFER = csdk::HAL_DMA_ERROR_TE;
@@ -54,25 +99,139 @@ impl Config {
         conf.init.Direction = csdk::DMA_MEMORY_TO_PERIPH;
         conf
     }
+
+    /// For [`copy`] and [`fill`], which set the alignment and increments themselves.
+    ///
+    /// Memory-to-memory transfers run without a peripheral request, see
+    /// [`DmaChannel::new_mem_to_mem`].
+    pub fn new_mem_to_mem() -> Self {
+        let mut conf = Self::new();
+        conf.init.Direction = csdk::DMA_MEMORY_TO_MEMORY;
+        conf.init.Mode = csdk::DMA_NORMAL;
+        conf
+    }
+}
+
+trait SealedWord {}
+
+/// Item of a memory-to-memory transfer.
+#[allow(private_bounds)]
+pub trait Word: SealedWord + Copy + 'static {
+    const PERIPH_ALIGN: u32;
+    const MEM_ALIGN: u32;
+}
+
+impl SealedWord for u8 {}
+impl Word for u8 {
+    const PERIPH_ALIGN: u32 = csdk::DMA_PDATAALIGN_BYTE;
+    const MEM_ALIGN: u32 = csdk::DMA_MDATAALIGN_BYTE;
+}
+
+impl SealedWord for u16 {}
+impl Word for u16 {
+    const PERIPH_ALIGN: u32 = csdk::DMA_PDATAALIGN_HALFWORD;
+    const MEM_ALIGN: u32 = csdk::DMA_MDATAALIGN_HALFWORD;
+}
+
+impl SealedWord for u32 {}
+impl Word for u32 {
+    const PERIPH_ALIGN: u32 = csdk::DMA_PDATAALIGN_WORD;
+    const MEM_ALIGN: u32 = csdk::DMA_MDATAALIGN_WORD;
+}
+
+/// Peripheral request served by a channel, mapped through `SYSCFG_CFGR3`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DmaRequest {
+    Adc = 0b00000,
+    Spi1Tx = 0b00001,
+    Spi1Rx = 0b00010,
+    Spi2Tx = 0b00011,
+    Spi2Rx = 0b00100,
+    Usart1Tx = 0b00101,
+    Usart1Rx = 0b00110,
+    Usart2Tx = 0b00111,
+    Usart2Rx = 0b01000,
+    I2cTx = 0b01001,
+    I2cRx = 0b01010,
+    Tim1Ch1 = 0b01011,
+    Tim1Ch2 = 0b01100,
+    Tim1Ch3 = 0b01101,
+    Tim1Ch4 = 0b01110,
+    Tim1Com = 0b01111,
+    Tim1Up = 0b10000,
+    Tim1Trig = 0b10001,
+    Tim3Ch1 = 0b10010,
+    Tim3Ch3 = 0b10011,
+    Tim3Ch4 = 0b10100,
+    Tim3Trig = 0b10101,
+    Tim3Up = 0b10110,
+    Tim16Ch1 = 0b11000,
+    Tim16Up = 0b11001,
+    Tim17Ch1 = 0b11010,
+    Tim17Up = 0b11011,
+}
+
+impl DmaRequest {
+    /// The request of a raw 5-bit `SYSCFG_CFGR3` code, `None` for reserved codes.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        use DmaRequest::*;
+        const ALL: [DmaRequest; 27] = [
+            Adc, Spi1Tx, Spi1Rx, Spi2Tx, Spi2Rx, Usart1Tx, Usart1Rx, Usart2Tx, Usart2Rx,
+            I2cTx, I2cRx, Tim1Ch1, Tim1Ch2, Tim1Ch3, Tim1Ch4, Tim1Com, Tim1Up, Tim1Trig,
+            Tim3Ch1, Tim3Ch3, Tim3Ch4, Tim3Trig, Tim3Up, Tim16Ch1, Tim16Up, Tim17Ch1, Tim17Up,
+        ];
+        ALL.into_iter().find(|request| *request as u8 == bits)
+    }
+}
+
+/// DMA1 channel.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DmaChannelId {
+    Ch1,
+    Ch2,
+    Ch3,
+}
+
+impl DmaChannelId {
+    /// The channel numbered `num`, counting from 1 like the reference manual.
+    pub fn from_num(num: u8) -> Option<Self> {
+        match num {
+            1 => Some(Self::Ch1),
+            2 => Some(Self::Ch2),
+            3 => Some(Self::Ch3),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
 }
 
 impl DmaChannel {
-    /// 00000：ADC 
-    /// 00001：SPI1_TX  00010：SPI1_RX 
-    /// 00011：SPI2_TX  00100：SPI2_RX 
-    /// 00101：USART1_TX  00110：USART1_RX 
-    /// 00111：USART2_TX  01000：USART2_RX 
-    /// 01001：I2C_TX  01010：I2C_RX 
-    /// 01011：TIM1_CH1  01100：TIM1_CH2  01101：TIM1_CH3  01110：TIM1_CH4 
-    /// 01111：TIM1_COM  10000：TIM1_UP  10001：TIM1_TRIG 
-    /// 10010：TIM3_CH1 10011：TIM3_CH3  10100：TIM3_CH4 
-    /// 10101：TIM3_TRG  10110：TIM3_UP 
-    /// 10111：Reserved
-    /// 11000：TIM16_CH1  11001：TIM16_UP  11010：TIM17_CH1 
-    /// 11011：TIM17_UP 
-    pub fn new(config: Config, channel: u8, map_value: u8) -> Result<Self, Error<DmaErrorFlags>> {
+    /// Set up `channel` for `request`.
+    ///
+    /// [`DmaChannel::link`] maps the channel to the request of the driver, so for a
+    /// peripheral channel `request` only matters until it is linked.
+    pub fn new(config: Config, channel: DmaChannelId, request: DmaRequest) -> Result<Self, Error<DmaErrorFlags>> {
+        Self::new_inner(config, channel, Some(request))
+    }
+
+    /// Set up `channel` for [`copy`] and [`fill`], leaving its request mapping alone.
+    pub fn new_mem_to_mem(channel: DmaChannelId) -> Result<Self, Error<DmaErrorFlags>> {
+        Self::new_inner(Config::new_mem_to_mem(), channel, None)
+    }
+
+    fn new_inner(
+        config: Config,
+        channel: DmaChannelId,
+        request: Option<DmaRequest>,
+    ) -> Result<Self, Error<DmaErrorFlags>> {
         let mut handle = csdk::DMA_HandleTypeDef {
-            Instance: csdk::DMA1_Channel1,
+            Instance: channel_instance(channel.index()),
             Init: config.init,
             Lock: 0,
             State: 0,
@@ -85,43 +244,376 @@ impl DmaChannel {
             DmaBaseAddress: core::ptr::null_mut(),
             ChannelIndex: 0,
         };
-        
+
         unsafe {
             csdk::HAL_RCC_DMA_CLK_ENABLE();
-
-            handle.Instance = match channel {
-                1 => {
-                    (*csdk::SYSCFG).CFGR3 &= !(0b11111);
-                    (*csdk::SYSCFG).CFGR3 |= map_value as u32;
-                    csdk::DMA1_Channel1
-                },
-                2 => {
-                    (*csdk::SYSCFG).CFGR3 &= !(0b11111 << 8);
-                    (*csdk::SYSCFG).CFGR3 |= (map_value as u32) << 8;
-                    csdk::DMA1_Channel2
-                },
-                3 => {
-                    (*csdk::SYSCFG).CFGR3 &= !(0b11111 << 16);
-                    (*csdk::SYSCFG).CFGR3 |= (map_value as u32) << 16;
-                    csdk::DMA1_Channel3
-                },
-                _ => panic!(),
-            };
+            if let Some(request) = request {
+                set_request(channel.index(), request);
+            }
             let result = csdk::HAL_DMA_Init(&mut handle);
             check(result, ||Error::HalError(DmaErrorFlags::from_bits_truncate(handle.ErrorCode)))?;
         }
         Ok(Self { handle })
     }
 
+    /// [`DmaChannel::new`] with the channel number (1..=3) and the raw 5-bit `SYSCFG_CFGR3`
+    /// code of the request.
+    pub fn new_from_raw(config: Config, channel: u8, map_value: u8) -> Result<Self, Error<DmaErrorFlags>> {
+        let channel = DmaChannelId::from_num(channel)
+            .ok_or(Error::UserInput(InputError::InvalidInstance))?;
+        let request = DmaRequest::from_bits(map_value)
+            .ok_or(Error::UserInput(InputError::InvalidDmaRequest))?;
+        Self::new(config, channel, request)
+    }
+
+    /// Map the channel to another peripheral request.
+    pub fn set_request(&mut self, request: DmaRequest) {
+        set_request(channel_index(self.handle.Instance), request);
+    }
+
+    /// Link the channel to a driver and register it with the DMA interrupt handler.
+    ///
+    /// Drivers map the channel to their own request, depending on its direction.
+    ///
+    /// The channel must not be moved while the driver uses it.
     pub fn link(&mut self, handle: &mut impl HasDmaField){
         handle.set_dma_field(self);
         self.handle.Parent = handle.get_handle_ptr();
+        self.register();
+    }
+
+    /// Register the channel with the DMA interrupt handler.
+    ///
+    /// The channel must not be moved while it is registered.
+    pub(crate) fn register(&mut self) {
+        unsafe {
+            DMA_CHANNELS[channel_index(self.handle.Instance)] = Some(&mut self.handle);
+        }
+    }
+
+    /// Start a transfer of `len` items from `src` to `dst` and return a future for it.
+    ///
+    /// The addresses are used as configured: `src` is the peripheral for
+    /// `DMA_PERIPH_TO_MEMORY`, `dst` for `DMA_MEMORY_TO_PERIPH`. Dropping the future before it
+    /// resolves aborts the transfer.
+    ///
+    /// # Safety
+    ///
+    /// Both addresses must stay valid for `len` items until the future resolves or is dropped.
+    pub unsafe fn start(&mut self, src: u32, dst: u32, len: u16) -> Result<Transfer<'_>, Error<DmaErrorFlags>> {
+        self.register();
+        let state = &STATES[channel_index(self.handle.Instance)];
+        state.take_events();
+        enable_irq(&mut self.handle);
+        check(csdk::HAL_DMA_Start_IT(&mut self.handle, src, dst, len as u32), ||self.gerr())?;
+        Ok(Transfer { channel: self, abort_on_drop: true })
+    }
+
+    /// Wait for a transfer started by the linked driver, e.g. `Adc::start_dma`.
+    ///
+    /// Resolves on the next transfer complete (also in circular mode), or right away if a
+    /// normal mode transfer is already done. A channel that was never started, or was
+    /// aborted, does not count as done. The channel interrupt is enabled from here on,
+    /// which for a circular transfer means one interrupt per lap.
+    pub fn wait(&mut self) -> Transfer<'_> {
+        self.register();
+        STATES[channel_index(self.handle.Instance)].take_events();
+        unsafe { enable_irq(&mut self.handle) };
+        Transfer { channel: self, abort_on_drop: false }
+    }
+
+    /// Set up the channel for byte-wise transfers between a peripheral data register and a buffer.
+    pub(crate) fn set_byte_buffer_mode(&mut self) -> Result<(), Error<DmaErrorFlags>> {
+        self.set_buffer_mode(false, true)
+    }
+
+    /// Set up the channel for transfers between a peripheral data register and a buffer of
+    /// half-words, or bytes if `half_word` is false.
+    ///
+    /// With `mem_inc` false the same memory word is read or written over and over,
+    /// used for dummy data.
+    pub(crate) fn set_buffer_mode(&mut self, half_word: bool, mem_inc: bool) -> Result<(), Error<DmaErrorFlags>> {
+        unsafe { set_buffer_mode(&mut self.handle, half_word, mem_inc) }
+    }
+
+    /// Switch between `DMA_NORMAL` and `DMA_CIRCULAR`.
+    pub(crate) fn set_mode(&mut self, mode: u32) -> Result<(), Error<DmaErrorFlags>> {
+        self.handle.Init.Mode = mode;
+        unsafe {
+            check(csdk::HAL_DMA_Init(&mut self.handle), ||self.gerr())
+        }
     }
 
     fn gerr(&self) -> Error<DmaErrorFlags> {
         Error::HalError(DmaErrorFlags::from_bits_truncate(self.handle.ErrorCode))
     }
 
+    /// Set up the channel for memory-to-memory transfers of `W`.
+    ///
+    /// The source sits on the peripheral side of the channel, with `src_inc` false the same
+    /// item is read over and over. The channel is only initialized again if its config changes.
+    fn set_mem_to_mem<W: Word>(&mut self, src_inc: bool) -> Result<(), Error<DmaErrorFlags>> {
+        let periph_inc = if src_inc { csdk::DMA_PINC_ENABLE } else { csdk::DMA_PINC_DISABLE };
+        let init = &mut self.handle.Init;
+        if init.Direction == csdk::DMA_MEMORY_TO_MEMORY
+            && init.PeriphInc == periph_inc
+            && init.MemInc == csdk::DMA_MINC_ENABLE
+            && init.PeriphDataAlignment == W::PERIPH_ALIGN
+            && init.MemDataAlignment == W::MEM_ALIGN
+            && init.Mode == csdk::DMA_NORMAL
+        {
+            return Ok(());
+        }
+        init.Direction = csdk::DMA_MEMORY_TO_MEMORY;
+        init.PeriphInc = periph_inc;
+        init.MemInc = csdk::DMA_MINC_ENABLE;
+        init.PeriphDataAlignment = W::PERIPH_ALIGN;
+        init.MemDataAlignment = W::MEM_ALIGN;
+        init.Mode = csdk::DMA_NORMAL;
+        unsafe {
+            check(csdk::HAL_DMA_Init(&mut self.handle), ||self.gerr())
+        }
+    }
+
+    /// Move `len` items from `src` to `dst`, in chunks of the 16-bit DMA count, and wait by
+    /// polling the channel flags.
+    fn blocking_mem_to_mem<W: Word>(
+        &mut self,
+        src: *const W,
+        src_inc: bool,
+        dst: *mut W,
+        len: usize,
+    ) -> Result<(), Error<DmaErrorFlags>> {
+        self.set_mem_to_mem::<W>(src_inc)?;
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(u16::MAX as usize);
+            let src = if src_inc { unsafe { src.add(done) } } else { src };
+            let dst = unsafe { dst.add(done) };
+            unsafe {
+                // the interrupt handler would clear the flags polled for
+                let ccr = &mut (*self.handle.Instance).CCR;
+                core::ptr::write_volatile(
+                    ccr,
+                    core::ptr::read_volatile(ccr) & !(csdk::DMA_IT_TC | csdk::DMA_IT_HT | csdk::DMA_IT_TE),
+                );
+                check(csdk::HAL_DMA_Start(&mut self.handle, src as u32, dst as u32, chunk as u32), ||self.gerr())?;
+                check(
+                    csdk::HAL_DMA_PollForTransfer(
+                        &mut self.handle,
+                        csdk::HAL_DMA_LevelCompleteTypeDef_HAL_DMA_FULL_TRANSFER,
+                        csdk::HAL_MAX_DELAY,
+                    ),
+                    ||self.gerr(),
+                )?;
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// [`DmaChannel::blocking_mem_to_mem`], waiting for the channel interrupt instead.
+    ///
+    /// Dropping the future aborts the chunk in flight.
+    async fn mem_to_mem<W: Word>(
+        &mut self,
+        src: *const W,
+        src_inc: bool,
+        dst: *mut W,
+        len: usize,
+    ) -> Result<(), Error<DmaErrorFlags>> {
+        self.set_mem_to_mem::<W>(src_inc)?;
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(u16::MAX as usize);
+            let src = if src_inc { unsafe { src.add(done) } } else { src };
+            let dst = unsafe { dst.add(done) };
+            unsafe { self.start(src as u32, dst as u32, chunk as u16)? }.await?;
+            done += chunk;
+        }
+        Ok(())
+    }
+}
+
+/// Copy `src` into `dst` on `channel`, blocking until done.
+///
+/// The channel is left set up for memory-to-memory transfers. Slices of different lengths are
+/// an `InputError::InvalidBufferLength`.
+pub fn blocking_copy<W: Word>(channel: &mut DmaChannel, src: &[W], dst: &mut [W]) -> Result<(), Error<DmaErrorFlags>> {
+    if src.len() != dst.len() {
+        return Err(Error::UserInput(InputError::InvalidBufferLength));
+    }
+    channel.blocking_mem_to_mem(src.as_ptr(), true, dst.as_mut_ptr(), dst.len())
+}
+
+/// Set every item of `dst` to `value` on `channel`, blocking until done.
+///
+/// The channel is left set up for memory-to-memory transfers.
+pub fn blocking_fill<W: Word>(channel: &mut DmaChannel, value: &W, dst: &mut [W]) -> Result<(), Error<DmaErrorFlags>> {
+    channel.blocking_mem_to_mem(value, false, dst.as_mut_ptr(), dst.len())
+}
+
+/// Copy `src` into `dst` on `channel`, while the CPU does something else.
+///
+/// The channel is left set up for memory-to-memory transfers. If the future is dropped
+/// early, `dst` may be partly written. Slices of different lengths are an
+/// `InputError::InvalidBufferLength`.
+pub async fn copy<W: Word>(channel: &mut DmaChannel, src: &[W], dst: &mut [W]) -> Result<(), Error<DmaErrorFlags>> {
+    if src.len() != dst.len() {
+        return Err(Error::UserInput(InputError::InvalidBufferLength));
+    }
+    channel.mem_to_mem(src.as_ptr(), true, dst.as_mut_ptr(), dst.len()).await
+}
+
+/// Set every item of `dst` to `value` on `channel`, while the CPU does something else.
+///
+/// The channel is left set up for memory-to-memory transfers. If the future is dropped
+/// early, `dst` may be partly written.
+pub async fn fill<W: Word>(channel: &mut DmaChannel, value: &W, dst: &mut [W]) -> Result<(), Error<DmaErrorFlags>> {
+    channel.mem_to_mem(value, false, dst.as_mut_ptr(), dst.len()).await
+}
+
+/// Map channel `index` to `request`, the register is shared by all channels.
+fn set_request(index: usize, request: DmaRequest) {
+    let shift = index * 8;
+    critical_section::with(|_| unsafe {
+        let cfgr3 = core::ptr::read_volatile(&(*csdk::SYSCFG).CFGR3);
+        let cfgr3 = (cfgr3 & !(0b11111 << shift)) | ((request as u32) << shift);
+        core::ptr::write_volatile(&mut (*csdk::SYSCFG).CFGR3, cfgr3);
+    });
+}
+
+fn channel_instance(index: usize) -> *mut csdk::DMA_Channel_TypeDef {
+    match index {
+        0 => csdk::DMA1_Channel1,
+        1 => csdk::DMA1_Channel2,
+        _ => csdk::DMA1_Channel3,
+    }
+}
+
+impl Drop for DmaChannel {
+    fn drop(&mut self) {
+        // the interrupt handler must not see a channel that is gone
+        let index = channel_index(self.handle.Instance);
+        critical_section::with(|_| unsafe {
+            if DMA_CHANNELS[index] == Some(&mut self.handle as *mut csdk::DMA_HandleTypeDef) {
+                DMA_CHANNELS[index] = None;
+            }
+        });
+    }
+}
+
+/// A running transfer of a [`DmaChannel`], resolves once it is complete or failed.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Transfer<'a> {
+    channel: &'a mut DmaChannel,
+    abort_on_drop: bool,
+}
+
+impl Transfer<'_> {
+    /// Items left to transfer.
+    pub fn remaining(&self) -> u16 {
+        unsafe { core::ptr::read_volatile(&(*self.channel.handle.Instance).CNDTR) as u16 }
+    }
+
+    /// Whether the transfer is done, without waiting.
+    pub fn is_complete(&self) -> bool {
+        // the channel is READY before its first start too, but only a finished transfer leaves
+        // it enabled: init and abort clear EN, completion does not
+        let ccr = unsafe { core::ptr::read_volatile(&(*self.channel.handle.Instance).CCR) };
+        let enabled = ccr & csdk::DMA_CCR_EN != 0;
+        self.channel.handle.Init.Mode == csdk::DMA_NORMAL
+            && enabled
+            && unsafe { core::ptr::read_volatile(&self.channel.handle.State) }
+                == csdk::HAL_DMA_StateTypeDef_HAL_DMA_STATE_READY
+    }
+}
+
+impl Future for Transfer<'_> {
+    type Output = Result<(), Error<DmaErrorFlags>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let state = &STATES[channel_index(self.channel.handle.Instance)];
+        state.waker.register(cx.waker());
+        let events = state.take_events();
+        let result = if events & EVENT_ERROR != 0 {
+            Err(Error::HalError(DmaErrorFlags::TRANSFER))
+        } else if events & EVENT_COMPLETE != 0 || self.is_complete() {
+            let error_code = unsafe { core::ptr::read_volatile(&self.channel.handle.ErrorCode) };
+            if error_code != csdk::HAL_DMA_ERROR_NONE {
+                Err(self.channel.gerr())
+            } else {
+                Ok(())
+            }
+        } else {
+            return Poll::Pending;
+        };
+        self.abort_on_drop = false;
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        if self.abort_on_drop {
+            unsafe {
+                csdk::HAL_DMA_Abort(&mut self.channel.handle);
+            }
+        }
+    }
+}
+
+pub(crate) fn channel_index(instance: *mut csdk::DMA_Channel_TypeDef) -> usize {
+    match instance {
+        csdk::DMA1_Channel1 => 0,
+        csdk::DMA1_Channel2 => 1,
+        _ => 2,
+    }
+}
+
+/// See [`DmaChannel::set_buffer_mode`], for drivers that only hold the linked handle.
+///
+/// The channel is only initialized again if its config changes.
+pub(crate) unsafe fn set_buffer_mode(
+    hdma: *mut csdk::DMA_HandleTypeDef,
+    half_word: bool,
+    mem_inc: bool,
+) -> Result<(), Error<DmaErrorFlags>> {
+    let (periph_align, mem_align) = if half_word {
+        (csdk::DMA_PDATAALIGN_HALFWORD, csdk::DMA_MDATAALIGN_HALFWORD)
+    } else {
+        (csdk::DMA_PDATAALIGN_BYTE, csdk::DMA_MDATAALIGN_BYTE)
+    };
+    let mem_inc = if mem_inc { csdk::DMA_MINC_ENABLE } else { csdk::DMA_MINC_DISABLE };
+
+    let init = &mut (*hdma).Init;
+    if init.PeriphInc == csdk::DMA_PINC_DISABLE
+        && init.MemInc == mem_inc
+        && init.PeriphDataAlignment == periph_align
+        && init.MemDataAlignment == mem_align
+        && init.Mode == csdk::DMA_NORMAL
+    {
+        return Ok(());
+    }
+    init.PeriphInc = csdk::DMA_PINC_DISABLE;
+    init.MemInc = mem_inc;
+    init.PeriphDataAlignment = periph_align;
+    init.MemDataAlignment = mem_align;
+    init.Mode = csdk::DMA_NORMAL;
+    check(csdk::HAL_DMA_Init(hdma), ||Error::HalError(DmaErrorFlags::from_bits_truncate((*hdma).ErrorCode)))
+}
+
+/// Enable the interrupt of the channel behind `hdma`.
+///
+/// Only drivers that rely on the CSDK DMA callbacks need this, a circular ADC transfer
+/// would otherwise interrupt on every conversion.
+pub(crate) unsafe fn enable_irq(hdma: *mut csdk::DMA_HandleTypeDef) {
+    let irqn: i32 = match (*hdma).Instance {
+        csdk::DMA1_Channel1 => csdk::IRQn_Type_DMA1_Channel1_IRQn,
+        _ => csdk::IRQn_Type_DMA1_Channel2_3_IRQn,
+    };
+    csdk::HAL_NVIC_SetPriority(irqn, 0, 0);
+    csdk::HAL_NVIC_EnableIRQ(irqn);
 }
 
 pub trait HasDmaField {
@@ -142,24 +634,25 @@ unsafe fn DMA1_CHANNEL2_3() {
 }
 
 unsafe fn on_irq() {
-    // WIP
-    let isr = (*csdk::DMA1).ISR;
-
-    let channel_id = if (isr & 1 ) != 0 { 
-        Some(0) 
-    } else if ( isr & (1 << 4) ) != 0 { 
-        Some(1)
-    } else if ( isr & (1 << 8) ) != 0 { 
-        Some(2)
-    } else {
-        None
-    };
-    defmt::println!("channel_id: {}", channel_id);
-    match channel_id {
-        Some(id) => match DMA_CHANNELS[id] {
+    let isr = core::ptr::read_volatile(&(*csdk::DMA1).ISR);
+
+    for index in 0..DMA_CHANNEL_COUNT {
+        let flags = isr >> (index * 4);
+        if flags & EVENT_GLOBAL == 0 {
+            continue;
+        }
+        // flags of disabled interrupts are set too, only report the enabled ones
+        let ccr = core::ptr::read_volatile(&(*channel_instance(index)).CCR);
+        let events = (flags & ccr) as u8 & (EVENT_COMPLETE | EVENT_HALF | EVENT_ERROR);
+        if events == 0 {
+            continue;
+        }
+        STATES[index].add_events(events);
+        match DMA_CHANNELS[index] {
+            // clears the flags and runs the callbacks of the linked driver
             Some(ptr) => csdk::HAL_DMA_IRQHandler(ptr),
-            None => (),
-        },
-        None => (),
+            None => (*csdk::DMA1).IFCR = (events as u32) << (index * 4),
+        }
+        STATES[index].waker.wake();
     }
-}
\ No newline at end of file
+}
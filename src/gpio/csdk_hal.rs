@@ -129,6 +129,20 @@ impl AnyPin{
         }
     }
 
+    /// Put the pin into open-drain output mode.
+    ///
+    /// The pin level will be whatever was set before (or low by default). The line is only
+    /// driven low, an external (or internal, see `set_as_af_od`) pull-up makes it high.
+    #[inline(never)]
+    pub fn set_as_output_od(&mut self, speed: Speed) {
+        self.c_init_type.Speed = speed.into();
+        self.c_init_type.Mode = csdk::GPIO_MODE_OUTPUT_OD;
+        unsafe {
+            csdk::HAL_GPIO_Init(self.port,
+                               &mut self.c_init_type as *mut csdk::GPIO_InitTypeDef);
+        }
+    }
+
     /// Put the pin into analog mode
     ///
     /// This mode is used by ADC and COMP but usually there is no need to set this manually
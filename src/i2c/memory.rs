@@ -0,0 +1,380 @@
+//! Register and memory access (`HAL_I2C_Mem_*`)
+
+use super::*;
+use crate::dma;
+
+/// Width of the register or memory address sent before the data.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemAddressSize {
+    /// One address byte, e.g. most sensors and 24C01..24C16 EEPROMs.
+    Bits8,
+    /// Two address bytes, MSB first, e.g. 24C32 and larger EEPROMs.
+    Bits16,
+}
+
+impl From<MemAddressSize> for u16 {
+    fn from(size: MemAddressSize) -> Self {
+        match size {
+            MemAddressSize::Bits8 => csdk::I2C_MEMADD_SIZE_8BIT as u16,
+            MemAddressSize::Bits16 => csdk::I2C_MEMADD_SIZE_16BIT as u16,
+        }
+    }
+}
+
+/// How an async memory access moves its data.
+#[derive(Copy, Clone)]
+enum Transport {
+    It,
+    Dma,
+}
+
+/// Splits a write at page boundaries.
+struct Pages<'a> {
+    mem_address: u16,
+    data: &'a [u8],
+    page_size: u16,
+}
+
+impl<'a> Pages<'a> {
+    fn new(mem_address: u16, data: &'a [u8], page_size: u16) -> Result<Self, Error<I2cErrorFlags>> {
+        if page_size == 0 {
+            return Err(Error::UserInput(InputError::InvalidBufferLength));
+        }
+        Ok(Self { mem_address, data, page_size })
+    }
+}
+
+impl<'a> Iterator for Pages<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let room = (self.page_size - self.mem_address % self.page_size) as usize;
+        let (chunk, rest) = self.data.split_at(room.min(self.data.len()));
+        let item = (self.mem_address, chunk);
+        self.mem_address = self.mem_address.wrapping_add(chunk.len() as u16);
+        self.data = rest;
+        Some(item)
+    }
+}
+
+impl<M: Mode> I2c<M> {
+    /// Read `read.len()` bytes starting at `register`.
+    pub fn blocking_read_register(
+        &mut self,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        let result = unsafe {
+            csdk::HAL_I2C_Mem_Read(
+                &mut self.handle,
+                (address as u16) << 1,
+                register,
+                size.into(),
+                read.as_mut_ptr(),
+                read.len() as u16,
+                self.get_timeout_tick(),
+            )
+        };
+        check(result, ||self.gerr())
+    }
+
+    /// Write `write` starting at `register`, in one transfer.
+    pub fn blocking_write_register(
+        &mut self,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        let result = unsafe {
+            csdk::HAL_I2C_Mem_Write(
+                &mut self.handle,
+                (address as u16) << 1,
+                register,
+                size.into(),
+                write.as_ptr() as *mut u8,
+                write.len() as u16,
+                self.get_timeout_tick(),
+            )
+        };
+        check(result, ||self.gerr())
+    }
+
+    /// Read an EEPROM-style memory. Sequential reads are not limited by pages.
+    pub fn blocking_read_memory(
+        &mut self,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        let mut mem_address = mem_address;
+        for chunk in read.chunks_mut(u16::MAX as usize) {
+            self.blocking_read_register(address, mem_address, size, chunk)?;
+            mem_address = mem_address.wrapping_add(chunk.len() as u16);
+        }
+        Ok(())
+    }
+
+    /// Write an EEPROM-style memory.
+    ///
+    /// The data is split at `page_size` boundaries, and after each page the device is polled
+    /// until it ACKs its address again (its internal write cycle is done).
+    /// A `page_size` of 0 is an `InputError::InvalidBufferLength`.
+    pub fn blocking_write_memory(
+        &mut self,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        page_size: u16,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        for (mem_address, page) in Pages::new(mem_address, write, page_size)? {
+            self.blocking_write_register(address, mem_address, size, page)?;
+            self.blocking_wait_write_cycle(address)?;
+        }
+        Ok(())
+    }
+
+    /// ACK polling: the device NACKs its address until the write cycle is done.
+    fn blocking_wait_write_cycle(&mut self, address: u8) -> Result<(), Error<I2cErrorFlags>> {
+        let tickstart = unsafe { csdk::HAL_GetTick() };
+        loop {
+            if self.device_ready_once(address) {
+                return Ok(());
+            }
+            if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > self.get_timeout_tick() {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    fn device_ready_once(&mut self, address: u8) -> bool {
+        let result = unsafe {
+            csdk::HAL_I2C_IsDeviceReady(&mut self.handle, (address as u16) << 1, 1, self.get_timeout_tick())
+        };
+        result == csdk::HAL_StatusTypeDef_HAL_OK
+    }
+}
+
+impl I2c<Async> {
+    /// Read `read.len()` bytes starting at `register`, interrupt-driven.
+    pub async fn read_register(
+        &mut self,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_read(Transport::It, address, register, size, read).await
+    }
+
+    /// Write `write` starting at `register`, interrupt-driven.
+    pub async fn write_register(
+        &mut self,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_write(Transport::It, address, register, size, write).await
+    }
+
+    /// Read `read.len()` bytes starting at `register` through the linked RX DMA channel.
+    pub async fn read_register_dma(
+        &mut self,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_read(Transport::Dma, address, register, size, read).await
+    }
+
+    /// Write `write` starting at `register` through the linked TX DMA channel.
+    pub async fn write_register_dma(
+        &mut self,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_write(Transport::Dma, address, register, size, write).await
+    }
+
+    /// Read an EEPROM-style memory, interrupt-driven.
+    pub async fn read_memory(
+        &mut self,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_read_chunked(Transport::It, address, mem_address, size, read).await
+    }
+
+    /// Write an EEPROM-style memory page by page with ACK polling, interrupt-driven.
+    pub async fn write_memory(
+        &mut self,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        page_size: u16,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_write_paged(Transport::It, address, mem_address, size, page_size, write).await
+    }
+
+    /// Read an EEPROM-style memory through the linked RX DMA channel.
+    pub async fn read_memory_dma(
+        &mut self,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_read_chunked(Transport::Dma, address, mem_address, size, read).await
+    }
+
+    /// Write an EEPROM-style memory page by page with ACK polling, through the linked TX DMA channel.
+    pub async fn write_memory_dma(
+        &mut self,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        page_size: u16,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.mem_write_paged(Transport::Dma, address, mem_address, size, page_size, write).await
+    }
+
+    async fn mem_read(
+        &mut self,
+        transport: Transport,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        if let Transport::Dma = transport {
+            self.prepare_dma(self.handle.hdmarx)?;
+        }
+        let start = match transport {
+            Transport::It => csdk::HAL_I2C_Mem_Read_IT,
+            Transport::Dma => csdk::HAL_I2C_Mem_Read_DMA,
+        };
+        self.run_it(|handle| unsafe {
+            start(handle, (address as u16) << 1, register, size.into(), read.as_mut_ptr(), read.len() as u16)
+        }).await
+    }
+
+    async fn mem_write(
+        &mut self,
+        transport: Transport,
+        address: u8,
+        register: u16,
+        size: MemAddressSize,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        if let Transport::Dma = transport {
+            self.prepare_dma(self.handle.hdmatx)?;
+        }
+        let start = match transport {
+            Transport::It => csdk::HAL_I2C_Mem_Write_IT,
+            Transport::Dma => csdk::HAL_I2C_Mem_Write_DMA,
+        };
+        self.run_it(|handle| unsafe {
+            start(handle, (address as u16) << 1, register, size.into(), write.as_ptr() as *mut u8, write.len() as u16)
+        }).await
+    }
+
+    async fn mem_read_chunked(
+        &mut self,
+        transport: Transport,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        let mut mem_address = mem_address;
+        for chunk in read.chunks_mut(u16::MAX as usize) {
+            self.mem_read(transport, address, mem_address, size, chunk).await?;
+            mem_address = mem_address.wrapping_add(chunk.len() as u16);
+        }
+        Ok(())
+    }
+
+    async fn mem_write_paged(
+        &mut self,
+        transport: Transport,
+        address: u8,
+        mem_address: u16,
+        size: MemAddressSize,
+        page_size: u16,
+        write: &[u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        for (mem_address, page) in Pages::new(mem_address, write, page_size)? {
+            self.mem_write(transport, address, mem_address, size, page).await?;
+            self.wait_write_cycle(address).await?;
+        }
+        Ok(())
+    }
+
+    /// ACK polling without blocking the executor, the attempts run through the IT path.
+    async fn wait_write_cycle(&mut self, address: u8) -> Result<(), Error<I2cErrorFlags>> {
+        let tickstart = unsafe { csdk::HAL_GetTick() };
+        loop {
+            match self.probe_address(address).await {
+                Ok(()) => return Ok(()),
+                Err(Error::HalError(flags)) if flags.contains(I2cErrorFlags::NACK_ADDRESS) => (),
+                Err(e) => return Err(e),
+            }
+            if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > self.get_timeout_tick() {
+                return Err(Error::Timeout);
+            }
+            #[cfg(feature = "time")]
+            embassy_time::Timer::after_micros(500).await;
+            #[cfg(not(feature = "time"))]
+            embassy_futures::yield_now().await;
+        }
+    }
+
+    /// Set the DMA channel up for a byte buffer and point it back at this handle, which may
+    /// have moved since `link`.
+    fn prepare_dma(&mut self, hdma: *mut csdk::DMA_HandleTypeDef) -> Result<(), Error<I2cErrorFlags>> {
+        if hdma.is_null() {
+            return Err(Error::UserInput(InputError::MissingDma));
+        }
+        unsafe {
+            dma::set_buffer_mode(hdma, false, true).map_err(|_| Error::HalError(I2cErrorFlags::DMA))?;
+            (*hdma).Parent = &mut self.handle as *mut csdk::I2C_HandleTypeDef as *mut core::ffi::c_void;
+            dma::enable_irq(hdma);
+        }
+        Ok(())
+    }
+}
+
+impl<M: Mode> dma::HasDmaField for I2c<M> {
+    /// A memory-to-peripheral channel becomes `hdmatx`, anything else `hdmarx`.
+    fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel) {
+        if dma_handle.handle.Init.Direction == csdk::DMA_MEMORY_TO_PERIPH {
+            dma_handle.set_request(dma::DmaRequest::I2cTx);
+            self.handle.hdmatx = &mut dma_handle.handle;
+        } else {
+            dma_handle.set_request(dma::DmaRequest::I2cRx);
+            self.handle.hdmarx = &mut dma_handle.handle;
+        }
+    }
+
+    fn get_handle_ptr(&mut self) -> *mut core::ffi::c_void {
+        &mut self.handle
+            as *mut csdk::I2C_HandleTypeDef
+            as *mut core::ffi::c_void
+    }
+}
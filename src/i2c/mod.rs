@@ -0,0 +1,940 @@
+//! Inter-Integrated-Circuit (I2C)
+#![macro_use]
+
+// modified from https://github.com/embassy-rs/embassy/
+// 94007ce6e0fc59e374902eadcc31616e56068e43
+
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+use core::task::Poll;
+
+use embedded_hal as embedded_hal_1;
+use embassy_sync::waitqueue::AtomicWaker;
+
+#[cfg(feature = "time")]
+use embassy_time::Duration;
+use defmt::bitflags;
+
+use csdk_hal::check;
+use crate::*;
+use crate::csdk::interrupts::interrupt;
+use crate::mode::{Async, Blocking, Mode};
+
+mod memory;
+pub use memory::*;
+mod scan;
+pub use scan::*;
+mod shared;
+pub use shared::*;
+mod target;
+pub use target::*;
+
+/// Per-instance interrupt state.
+///
+/// `handle` points at the driver's `I2C_HandleTypeDef` only while an IT transfer is running,
+/// so the interrupt handler never touches a handle that may have been moved.
+struct State {
+    waker: AtomicWaker,
+    handle: AtomicPtr<csdk::I2C_HandleTypeDef>,
+    /// Last address match seen in target mode, see [`target::Command`].
+    command: AtomicU8,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            handle: AtomicPtr::new(core::ptr::null_mut()),
+            command: AtomicU8::new(target::NO_COMMAND),
+        }
+    }
+}
+
+const I2C_COUNT: usize = 2;
+static STATES: [State; I2C_COUNT] = [State::new(), State::new()];
+
+unsafe fn on_irq(index: usize) {
+    let state = &STATES[index];
+    let handle = state.handle.load(Ordering::Relaxed);
+    if !handle.is_null() {
+        csdk::HAL_I2C_EV_IRQHandler(handle);
+        csdk::HAL_I2C_ER_IRQHandler(handle);
+    }
+    state.waker.wake();
+}
+
+/// Abort an IT or DMA transfer started through `handle`.
+unsafe fn abort_it(handle: *mut csdk::I2C_HandleTypeDef) {
+    let status = csdk::HAL_I2C_Master_Abort_IT(handle, (*handle).Devaddress as u16);
+    if status != csdk::HAL_StatusTypeDef_HAL_OK {
+        // memory transfers and target frames cannot be aborted through the CSDK,
+        // reset the peripheral instead
+        if !(*handle).hdmatx.is_null() {
+            csdk::HAL_DMA_Abort((*handle).hdmatx);
+        }
+        if !(*handle).hdmarx.is_null() {
+            csdk::HAL_DMA_Abort((*handle).hdmarx);
+        }
+        csdk::HAL_I2C_DeInit(handle);
+        csdk::HAL_I2C_Init(handle);
+    }
+}
+
+unsafe fn wake(hi2c: *mut csdk::I2C_HandleTypeDef) {
+    STATES[state_index((*hi2c).Instance)].waker.wake();
+}
+
+// DMA transfers may finish (or fail) in the DMA interrupt, wake from the CSDK callbacks too.
+
+#[no_mangle]
+unsafe extern "C" fn HAL_I2C_MemRxCpltCallback(hi2c: *mut csdk::I2C_HandleTypeDef) {
+    wake(hi2c);
+}
+
+#[no_mangle]
+unsafe extern "C" fn HAL_I2C_MemTxCpltCallback(hi2c: *mut csdk::I2C_HandleTypeDef) {
+    wake(hi2c);
+}
+
+#[no_mangle]
+unsafe extern "C" fn HAL_I2C_ErrorCallback(hi2c: *mut csdk::I2C_HandleTypeDef) {
+    wake(hi2c);
+}
+
+#[cfg(any(feature = "peri-i2c0", feature = "peri-i2c1"))]
+#[interrupt]
+unsafe fn I2C1() {
+    on_irq(0);
+}
+
+#[cfg(feature = "peri-i2c2")]
+#[interrupt]
+unsafe fn I2C2() {
+    on_irq(1);
+}
+
+
+
+
+
+// I2C error.
+// #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+// #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+// pub enum I2cError {
+//     /// Bus error
+//     Bus,
+//     /// Arbitration lost
+//     Arbitration,
+//     /// ACK not received (either to the address or to a data byte)
+//     Nack,
+//     /// Timeout
+//     Timeout,
+//     /// CRC error
+//     Crc,
+//     /// Overrun error
+//     Overrun,
+//     #[cfg(feature = "peri-dma")]
+//     /// DMA transfer error.
+//     Dma,
+//     #[cfg(feature = "peri-dma")]
+//     /// DMA parameter error.
+//     DmaParam,
+//     /// Size management error.
+//     Size,
+//     /// CSDK error
+//     Csdk,
+//     /// Other error
+//     Other,
+// }
+
+bitflags! {
+    pub struct I2cErrorFlags: u32 {
+        const NONE      = csdk::HAL_I2C_ERROR_NONE;
+        const BUS       = csdk::HAL_I2C_ERROR_BERR;
+        const ARBITRATION = csdk::HAL_I2C_ERROR_ARLO;
+        const NACK      = csdk::HAL_I2C_ERROR_AF;
+        const OVERRUN   = csdk::HAL_I2C_ERROR_OVR;
+        const TIMEOUT   = csdk::HAL_I2C_ERROR_TIMEOUT;
+        const SIZE      = csdk::HAL_I2C_ERROR_SIZE;
+        #[cfg(feature = "peri-dma")]
+        const DMA       = csdk::HAL_I2C_ERROR_DMA;
+        #[cfg(feature = "peri-dma")]
+        const DMA_PARAM = csdk::HAL_I2C_ERROR_DMA_PARAM;
+        /// Not a CSDK error code: set with `NACK` when the address was not acknowledged.
+        const NACK_ADDRESS = 1 << 16;
+        /// Not a CSDK error code: set with `NACK` when a data byte was not acknowledged.
+        const NACK_DATA = 1 << 17;
+    }
+}
+
+impl I2cErrorFlags {
+    /// Decode `ErrorCode` right after a failed transfer, telling apart address and data NACKs.
+    fn from_handle(handle: &csdk::I2C_HandleTypeDef) -> Self {
+        let mut flags = Self::from_bits_truncate(handle.ErrorCode);
+        if flags.contains(Self::NACK) {
+            // `XferCount` is counted down as bytes go out,
+            // so it is untouched if the address phase already failed.
+            if handle.XferCount == handle.XferSize {
+                flags |= Self::NACK_ADDRESS;
+            } else {
+                flags |= Self::NACK_DATA;
+            }
+        }
+        flags
+    }
+
+    /// The most specific [`embedded_hal_1::i2c::ErrorKind`] for a set of flags.
+    pub fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource};
+
+        if self.contains(Self::BUS) {
+            ErrorKind::Bus
+        } else if self.contains(Self::ARBITRATION) {
+            ErrorKind::ArbitrationLoss
+        } else if self.contains(Self::NACK) {
+            let source = if self.contains(Self::NACK_ADDRESS) {
+                NoAcknowledgeSource::Address
+            } else if self.contains(Self::NACK_DATA) {
+                NoAcknowledgeSource::Data
+            } else {
+                NoAcknowledgeSource::Unknown
+            };
+            ErrorKind::NoAcknowledge(source)
+        } else if self.contains(Self::OVERRUN) {
+            ErrorKind::Overrun
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+
+/// I2C config
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct Config {
+    pub init: csdk::I2C_InitTypeDef,
+    /// Timeout.
+    #[cfg(feature = "time")]
+    pub timeout: Duration,
+    #[cfg(not(feature = "time"))]
+    pub timeout_tick: u32,
+    /// SCL pin, in its AF configuration. Needed by [`I2c::recover_bus`].
+    pub scl: Option<gpio::AnyPin>,
+    /// SDA pin, in its AF configuration. Needed by [`I2c::recover_bus`].
+    pub sda: Option<gpio::AnyPin>,
+    /// Run [`I2c::recover_bus`] after a bus error or a timeout, and when the
+    /// peripheral reports busy at init.
+    pub auto_recover: bool,
+    /// Used by `probe` and `scan`.
+    pub probe: ProbeConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            init: csdk::I2C_InitTypeDef {
+                ClockSpeed: 100000,
+                DutyCycle: csdk::I2C_DUTYCYCLE_16_9,
+                OwnAddress1: 0xA0,
+                GeneralCallMode: csdk::I2C_GENERALCALL_DISABLE,
+                NoStretchMode: csdk::I2C_NOSTRETCH_DISABLE,
+            },
+            #[cfg(feature = "time")]
+            timeout: Duration::from_secs(2),
+            #[cfg(not(feature = "time"))]
+            timeout_tick: 2000,
+            scl: None,
+            sda: None,
+            auto_recover: false,
+            probe: Default::default(),
+        }
+    }
+}
+
+pub struct I2c<M: Mode> {
+    scl: Option<gpio::AnyPin>,
+    sda: Option<gpio::AnyPin>,
+    auto_recover: bool,
+    probe: ProbeConfig,
+    pub handle: csdk::I2C_HandleTypeDef,
+    /// Timeout.
+    #[cfg(feature = "time")]
+    pub timeout: Duration,
+    #[cfg(not(feature = "time"))]
+    timeout_tick: u32,
+    _phantom: PhantomData<M>,
+}
+
+impl I2c<Blocking> {
+    /// Create a new blocking I2C driver.
+    pub fn new_blocking_from_csdk(instance: *mut csdk::I2C_TypeDef, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        Self::new_inner(instance, config)
+    }
+
+    #[cfg(feature = "peri-i2c0")]
+    pub fn new_blocking(config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let instance = csdk::I2C;
+        Self::new_inner(instance, config)
+    }
+
+    #[cfg(not(feature = "peri-i2c0"))]
+    pub fn new_blocking(instance_num: u8, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_inner(instance, config)
+    }
+}
+
+impl I2c<Async> {
+    /// Create a new interrupt-driven I2C driver.
+    pub fn new_from_csdk(instance: *mut csdk::I2C_TypeDef, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let this = Self::new_inner(instance, config)?;
+        enable_irq(this.handle.Instance);
+        Ok(this)
+    }
+
+    #[cfg(feature = "peri-i2c0")]
+    pub fn new(config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let instance = csdk::I2C;
+        Self::new_from_csdk(instance, config)
+    }
+
+    #[cfg(not(feature = "peri-i2c0"))]
+    pub fn new(instance_num: u8, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, config)
+    }
+
+    /// Register the handle with the interrupt handler, start an IT transfer and wait for it.
+    ///
+    /// If the returned future is dropped (or times out) before the transfer is done,
+    /// the transfer is aborted.
+    async fn run_it<F>(&mut self, start: F) -> Result<(), Error<I2cErrorFlags>>
+    where
+        F: FnOnce(*mut csdk::I2C_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+    {
+        #[cfg(feature = "time")]
+        let result = self.run_it_inner(self.timeout, start).await;
+        #[cfg(not(feature = "time"))]
+        let result = self.run_it_inner(start).await;
+        self.recover_on_error(result)
+    }
+
+    async fn run_it_inner<F>(
+        &mut self,
+        #[cfg(feature = "time")] timeout: Duration,
+        start: F,
+    ) -> Result<(), Error<I2cErrorFlags>>
+    where
+        F: FnOnce(*mut csdk::I2C_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+    {
+        let index = state_index(self.handle.Instance);
+        let handle = &mut self.handle as *mut csdk::I2C_HandleTypeDef;
+        STATES[index].handle.store(handle, Ordering::Relaxed);
+
+        if let Err(e) = check(start(handle), || self.gerr()) {
+            STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+            return Err(e);
+        }
+
+        let on_drop = OnDrop::new(|| unsafe {
+            abort_it(handle);
+            STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        });
+
+        let wait = poll_fn(|cx| {
+            STATES[index].waker.register(cx.waker());
+            let state = unsafe { core::ptr::read_volatile(&(*handle).State) };
+            if state == csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_READY {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+
+        #[cfg(feature = "time")]
+        if embassy_time::with_timeout(timeout, wait).await.is_err() {
+            drop(on_drop);
+            return Err(Error::Timeout);
+        }
+        #[cfg(not(feature = "time"))]
+        wait.await;
+
+        on_drop.defuse();
+        STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+
+        let error_code = unsafe { core::ptr::read_volatile(&(*handle).ErrorCode) };
+        if error_code != csdk::HAL_I2C_ERROR_NONE {
+            return Err(self.gerr());
+        }
+        Ok(())
+    }
+
+    /// Read from the device, waiting on the I2C interrupt instead of polling.
+    pub async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Error<I2cErrorFlags>> {
+        self.run_it(|handle| unsafe {
+            csdk::HAL_I2C_Master_Receive_IT(
+                handle,
+                (address as u16) << 1,
+                read.as_mut_ptr(),
+                read.len() as u16,
+            )
+        }).await
+    }
+
+    /// Write to the device, waiting on the I2C interrupt instead of polling.
+    pub async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Error<I2cErrorFlags>> {
+        self.run_it(|handle| unsafe {
+            csdk::HAL_I2C_Master_Transmit_IT(
+                handle,
+                (address as u16) << 1,
+                write.as_ptr() as *mut u8,
+                write.len() as u16,
+            )
+        }).await
+    }
+
+    /// Write then read with a repeated START in between.
+    pub async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        self.transaction(address, &mut [
+            embedded_hal_1::i2c::Operation::Write(write),
+            embedded_hal_1::i2c::Operation::Read(read),
+        ]).await
+    }
+
+    /// Run the operations as one bus transaction, see [`embedded_hal_1::i2c::I2c::transaction`].
+    ///
+    /// An empty write only sends the address. An empty read needs a non-empty read next to it,
+    /// or it is an `InputError::InvalidBufferLength`. If all operations are empty, the address
+    /// is probed.
+    pub async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        if operations.iter().all(op_is_empty) {
+            return self.probe_address(address).await;
+        }
+        check_empty_reads(operations)?;
+        let mut prev = None;
+        let mut ops = operations.iter_mut().filter(|op| !is_merged_read(op)).peekable();
+        while let Some(op) = ops.next() {
+            let options = xfer_options(prev, op_is_read(op), ops.peek().map(|op| op_is_read(op)));
+            prev = Some(op_is_read(op));
+            self.run_it(|handle| unsafe { start_seq(handle, address, op, options) }).await?;
+        }
+        Ok(())
+    }
+
+    /// Address the device without transferring any data.
+    async fn probe_address(&mut self, address: u8) -> Result<(), Error<I2cErrorFlags>> {
+        self.run_it(|handle| unsafe { start_probe(handle, address) }).await
+    }
+}
+
+/// `XferOptions` for one frame of a sequential transfer.
+///
+/// Adjacent operations in the same direction are merged into one frame sequence (no START, no
+/// address), a change of direction gets a repeated START, and only the last frame generates STOP.
+/// The CSDK decides on the repeated START from the previous frame's direction; the options here
+/// only pick how the frame ends.
+fn xfer_options(prev: Option<bool>, is_read: bool, next: Option<bool>) -> u32 {
+    match (prev, next) {
+        (None, None) => csdk::I2C_FIRST_AND_LAST_FRAME,
+        (Some(_), None) => csdk::I2C_LAST_FRAME,
+        // NACK the last byte of a read before the direction changes, without a STOP
+        (_, Some(next)) if is_read && !next => csdk::I2C_LAST_FRAME_NO_STOP,
+        (None, Some(_)) => csdk::I2C_FIRST_FRAME,
+        (Some(_), Some(_)) => csdk::I2C_NEXT_FRAME,
+    }
+}
+
+fn op_is_read(op: &embedded_hal_1::i2c::Operation<'_>) -> bool {
+    matches!(op, embedded_hal_1::i2c::Operation::Read(_))
+}
+
+fn op_is_empty(op: &embedded_hal_1::i2c::Operation<'_>) -> bool {
+    match op {
+        embedded_hal_1::i2c::Operation::Read(read) => read.is_empty(),
+        embedded_hal_1::i2c::Operation::Write(write) => write.is_empty(),
+    }
+}
+
+/// An empty read is merged into the reads next to it and never goes on the bus.
+fn is_merged_read(op: &embedded_hal_1::i2c::Operation<'_>) -> bool {
+    op_is_read(op) && op_is_empty(op)
+}
+
+/// Reject empty reads that have no non-empty read next to them to merge into.
+///
+/// Empty writes are sent as zero-length frames, which still address the device, but a read
+/// frame has to clock at least one byte once its address is ACKed.
+fn check_empty_reads(operations: &[embedded_hal_1::i2c::Operation<'_>]) -> Result<(), Error<I2cErrorFlags>> {
+    let mut rest = operations;
+    while let Some(first) = rest.first() {
+        let run = rest.iter().take_while(|op| op_is_read(op) == op_is_read(first)).count();
+        let (group, tail) = rest.split_at(run);
+        if op_is_read(first) && group.iter().all(op_is_empty) {
+            return Err(Error::UserInput(InputError::InvalidBufferLength));
+        }
+        rest = tail;
+    }
+    Ok(())
+}
+
+/// Start a zero-length write, which only sends the address.
+unsafe fn start_probe(handle: *mut csdk::I2C_HandleTypeDef, address: u8) -> csdk::HAL_StatusTypeDef {
+    csdk::HAL_I2C_Master_Transmit_IT(handle, (address as u16) << 1, core::ptr::NonNull::dangling().as_ptr(), 0)
+}
+
+/// Start one frame of a sequential transfer.
+unsafe fn start_seq(
+    handle: *mut csdk::I2C_HandleTypeDef,
+    address: u8,
+    op: &mut embedded_hal_1::i2c::Operation<'_>,
+    options: u32,
+) -> csdk::HAL_StatusTypeDef {
+    match op {
+        embedded_hal_1::i2c::Operation::Read(read) => csdk::HAL_I2C_Master_Seq_Receive_IT(
+            handle,
+            (address as u16) << 1,
+            read.as_mut_ptr(),
+            read.len() as u16,
+            options,
+        ),
+        embedded_hal_1::i2c::Operation::Write(write) => csdk::HAL_I2C_Master_Seq_Transmit_IT(
+            handle,
+            (address as u16) << 1,
+            write.as_ptr() as *mut u8,
+            write.len() as u16,
+            options,
+        ),
+    }
+}
+
+#[cfg(not(feature = "peri-i2c0"))]
+fn instance_from_num(instance_num: u8) -> Result<*mut csdk::I2C_TypeDef, Error<I2cErrorFlags>> {
+    match instance_num {
+        #[cfg(feature = "peri-i2c1")]
+        1 => Ok(csdk::I2C1),
+        #[cfg(feature = "peri-i2c2")]
+        2 => Ok(csdk::I2C2),
+        _ => Err(Error::UserInput(InputError::InvalidInstance)),
+    }
+}
+
+fn enable_irq(instance: *mut csdk::I2C_TypeDef) {
+    let irqn: i32 = match instance {
+        #[cfg(feature = "peri-i2c0")]
+        csdk::I2C => csdk::IRQn_Type_I2C1_IRQn,
+        #[cfg(feature = "peri-i2c1")]
+        csdk::I2C1 => csdk::IRQn_Type_I2C1_IRQn,
+        #[cfg(feature = "peri-i2c2")]
+        csdk::I2C2 => csdk::IRQn_Type_I2C2_IRQn,
+        // the instance was checked by `enable_and_init`
+        _ => unreachable!(),
+    };
+    unsafe {
+        csdk::HAL_NVIC_SetPriority(irqn, 0, 0);
+        csdk::HAL_NVIC_EnableIRQ(irqn);
+    }
+}
+
+fn state_index(instance: *mut csdk::I2C_TypeDef) -> usize {
+    match instance {
+        #[cfg(feature = "peri-i2c2")]
+        csdk::I2C2 => 1,
+        _ => 0,
+    }
+}
+
+impl<M: Mode> I2c<M> {
+    /// Create a new I2C driver.
+    fn new_inner(instance: *mut csdk::I2C_TypeDef, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let handle = csdk::I2C_HandleTypeDef {
+            Instance: instance,
+            Init: config.init,
+            pBuffPtr: core::ptr::null_mut(),
+            XferSize: 0,
+            XferCount: 0,
+            XferOptions: 0,
+            PreviousState: 0,
+            hdmatx: core::ptr::null_mut(),
+            hdmarx: core::ptr::null_mut(),
+            Lock: 0,
+            State: 0,
+            Mode: 0,
+            ErrorCode: 0,
+            Devaddress: 0,
+            Memaddress: 0,
+            MemaddSize: 0,
+            EventCount: 0,
+        };
+        
+        let mut this = Self {
+            scl: config.scl,
+            sda: config.sda,
+            auto_recover: config.auto_recover,
+            probe: config.probe,
+            handle,
+            #[cfg(feature = "time")]
+            timeout: config.timeout,
+            #[cfg(not(feature = "time"))]
+            timeout_tick: config.timeout_tick,
+            _phantom: Default::default(),
+        };
+        match this.enable_and_init() {
+            Err(Error::Busy) if this.auto_recover => this.recover_bus()?,
+            result => result?,
+        }
+        Ok(this)
+    }
+
+    /// Free a bus that a device holds by keeping SDA low, e.g. after a transfer was
+    /// interrupted mid-byte.
+    ///
+    /// SCL and SDA are taken over as open-drain GPIOs, SCL is pulsed (at most nine times) until
+    /// the device releases SDA, and a STOP is generated. Then the AF configuration of the pins
+    /// is restored and the peripheral is reset and initialized again.
+    ///
+    /// Needs `Config::scl` and `Config::sda`. Returns `Error::Busy` if SDA is still held low.
+    pub fn recover_bus(&mut self) -> Result<(), Error<I2cErrorFlags>> {
+        if self.scl.is_none() || self.sda.is_none() {
+            return Err(Error::UserInput(InputError::MissingPin));
+        }
+        let mut scl = self.scl.take().unwrap();
+        let mut sda = self.sda.take().unwrap();
+        let scl_af = scl.c_init_type;
+        let sda_af = sda.c_init_type;
+
+        unsafe {
+            csdk::HAL_I2C_DeInit(&mut self.handle);
+        }
+
+        scl.set_high();
+        sda.set_high();
+        scl.set_as_output_od(gpio::Speed::High);
+        sda.set_as_output_od(gpio::Speed::High);
+
+        let half_period_us = (500_000 / self.handle.Init.ClockSpeed).max(1);
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low();
+            csdk_hal::delay_us(half_period_us);
+            scl.set_high();
+            csdk_hal::delay_us(half_period_us);
+            // the device may stretch the clock
+            for _ in 0..100 {
+                if scl.is_high() {
+                    break;
+                }
+                csdk_hal::delay_us(half_period_us);
+            }
+        }
+
+        // STOP: SDA rises while SCL is high
+        sda.set_low();
+        csdk_hal::delay_us(half_period_us);
+        scl.set_high();
+        csdk_hal::delay_us(half_period_us);
+        sda.set_high();
+        csdk_hal::delay_us(half_period_us);
+        let released = sda.is_high();
+
+        for (pin, af) in [(&mut scl, scl_af), (&mut sda, sda_af)] {
+            pin.c_init_type = af;
+            unsafe {
+                csdk::HAL_GPIO_Init(pin.port, &mut pin.c_init_type);
+            }
+        }
+        self.scl = Some(scl);
+        self.sda = Some(sda);
+
+        self.enable_and_init()?;
+        if !released {
+            return Err(Error::Busy);
+        }
+        Ok(())
+    }
+
+    /// Run [`recover_bus`](Self::recover_bus) if `auto_recover` is set and `result` looks like
+    /// a stuck bus. The original error is returned either way.
+    fn recover_on_error<T>(&mut self, result: Result<T, Error<I2cErrorFlags>>) -> Result<T, Error<I2cErrorFlags>> {
+        let stuck = match &result {
+            Err(Error::Timeout) => true,
+            Err(Error::HalError(flags)) => flags.intersects(I2cErrorFlags::BUS | I2cErrorFlags::TIMEOUT),
+            _ => false,
+        };
+        if stuck && self.auto_recover {
+            let _ = self.recover_bus();
+        }
+        result
+    }
+
+    fn enable_and_init(&mut self) -> Result<(), Error<I2cErrorFlags>> {
+        unsafe{
+            match self.handle.Instance {
+                #[cfg(feature = "peri-i2c0")]
+                csdk::I2C => {
+                    csdk::HAL_RCC_I2C_CLK_ENABLE();
+                    csdk::HAL_RCC_I2C_FORCE_RESET();
+                    csdk::HAL_RCC_I2C_RELEASE_RESET();
+                    Ok(())
+                },
+                #[cfg(feature = "peri-i2c1")]
+                csdk::I2C1 => {
+                    csdk::HAL_RCC_I2C1_CLK_ENABLE();
+                    csdk::HAL_RCC_I2C1_FORCE_RESET();
+                    csdk::HAL_RCC_I2C1_RELEASE_RESET();
+                    Ok(())
+                },
+                #[cfg(feature = "peri-i2c2")]
+                csdk::I2C2 => {
+                    csdk::HAL_RCC_I2C2_CLK_ENABLE();
+                    csdk::HAL_RCC_I2C2_FORCE_RESET();
+                    csdk::HAL_RCC_I2C2_RELEASE_RESET();
+                    Ok(())
+                },
+                _ => Err(Error::UserInput(InputError::InvalidInstance)),
+            }?;
+            check(csdk::HAL_I2C_Init(&mut self.handle), ||self.gerr())
+        }
+    }
+}
+
+// fn gerr(errorcode: u32) -> Error<I2cError> {
+//     // TODO: multi error code
+//     let err = match errorcode {
+//         csdk::HAL_I2C_ERROR_NONE => I2cError::Csdk,
+//         csdk::HAL_I2C_ERROR_BERR => I2cError::Bus,
+//         csdk::HAL_I2C_ERROR_ARLO => I2cError::Arbitration,
+//         csdk::HAL_I2C_ERROR_AF => I2cError::Nack,
+//         csdk::HAL_I2C_ERROR_OVR => I2cError::Overrun,
+//         #[cfg(feature = "peri-dma")]
+//         csdk::HAL_I2C_ERROR_DMA => I2cError::Dma,
+//         #[cfg(feature = "peri-dma")]
+//         csdk::HAL_I2C_ERROR_DMA_PARAM => I2cError::DmaParam,
+//         csdk::HAL_I2C_ERROR_TIMEOUT => I2cError::Timeout,
+//         csdk::HAL_I2C_ERROR_SIZE => I2cError::Size,
+//         _ => I2cError::Other,
+//     };
+//     Error::Error(err)
+// }
+
+
+impl<M: Mode> I2c<M> {
+    fn gerr(&self) -> Error<I2cErrorFlags> {
+        Error::HalError(I2cErrorFlags::from_handle(&self.handle))
+    }
+
+    fn get_timeout_tick(&self) -> u32 {
+        #[cfg(feature = "time")]
+        let timout_tick = self.timeout.as_ticks() as u32;
+        #[cfg(not(feature = "time"))]
+        let timout_tick = self.timeout_tick;
+        timout_tick
+    }
+
+    fn blocking_read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Error<I2cErrorFlags>> {
+        let result = unsafe {
+            csdk::HAL_I2C_Master_Receive(
+                &mut self.handle,
+                (address as u16) << 1,
+                read.as_mut_ptr(),
+                read.len() as u16,
+                self.get_timeout_tick(),
+            )
+        };
+        check(result, ||self.gerr())
+    }
+
+    fn blocking_write(&mut self, address: u8, write: &[u8]) -> Result<(), Error<I2cErrorFlags>> {
+        let result = unsafe {
+            csdk::HAL_I2C_Master_Transmit(
+                &mut self.handle,
+                (address as u16) << 1,
+                write.as_ptr() as *mut u8,
+                write.len() as u16,
+                self.get_timeout_tick(),
+            )
+        };
+        check(result, ||self.gerr())
+    }
+
+    /// Start an IT transfer and drive it to completion without relying on the NVIC.
+    ///
+    /// The CSDK interrupt handlers are polled from here, so the `_IT` (and sequential) API works
+    /// for blocking drivers too. If the interrupt is enabled, the handler serves the same handle
+    /// and polling it again is harmless.
+    fn blocking_run_it<F>(&mut self, start: F) -> Result<(), Error<I2cErrorFlags>>
+    where
+        F: FnOnce(*mut csdk::I2C_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+    {
+        let index = state_index(self.handle.Instance);
+        let handle = &mut self.handle as *mut csdk::I2C_HandleTypeDef;
+        STATES[index].handle.store(handle, Ordering::Relaxed);
+
+        let result = check(start(handle), || self.gerr()).and_then(|_| {
+            let tickstart = unsafe { csdk::HAL_GetTick() };
+            loop {
+                let state = unsafe { core::ptr::read_volatile(&(*handle).State) };
+                if state == csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_READY {
+                    break;
+                }
+                if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > self.get_timeout_tick() {
+                    unsafe { abort_it(handle) };
+                    return Err(Error::Timeout);
+                }
+                critical_section::with(|_| unsafe {
+                    csdk::HAL_I2C_EV_IRQHandler(handle);
+                    csdk::HAL_I2C_ER_IRQHandler(handle);
+                });
+            }
+            let error_code = unsafe { core::ptr::read_volatile(&(*handle).ErrorCode) };
+            if error_code != csdk::HAL_I2C_ERROR_NONE {
+                return Err(self.gerr());
+            }
+            Ok(())
+        });
+
+        STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        result
+    }
+
+    /// Address the device without transferring any data.
+    fn blocking_probe_address(&mut self, address: u8) -> Result<(), Error<I2cErrorFlags>> {
+        let result = unsafe {
+            csdk::HAL_I2C_IsDeviceReady(
+                &mut self.handle,
+                (address as u16) << 1,
+                1,
+                self.get_timeout_tick(),
+            )
+        };
+        check(result, || Error::HalError(I2cErrorFlags::NACK | I2cErrorFlags::NACK_ADDRESS))
+    }
+
+    /// Write then read with a repeated START in between.
+    ///
+    /// Register-style accesses (one or two bytes written) go through `HAL_I2C_Mem_Read`,
+    /// everything else through the sequential transfer API.
+    fn blocking_write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        let mem = match write {
+            [reg] => Some((*reg as u16, MemAddressSize::Bits8)),
+            [hi, lo] => Some((u16::from_be_bytes([*hi, *lo]), MemAddressSize::Bits16)),
+            _ => None,
+        };
+        match mem {
+            Some((mem_address, size)) if !read.is_empty() => {
+                self.blocking_read_register(address, mem_address, size, read)
+            },
+            _ => self.blocking_transaction(address, &mut [
+                embedded_hal_1::i2c::Operation::Write(write),
+                embedded_hal_1::i2c::Operation::Read(read),
+            ]),
+        }
+    }
+
+    fn blocking_transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Error<I2cErrorFlags>> {
+        if operations.iter().all(op_is_empty) {
+            return self.blocking_probe_address(address);
+        }
+        check_empty_reads(operations)?;
+        let mut prev = None;
+        let mut ops = operations.iter_mut().filter(|op| !is_merged_read(op)).peekable();
+        while let Some(op) = ops.next() {
+            let options = xfer_options(prev, op_is_read(op), ops.peek().map(|op| op_is_read(op)));
+            prev = Some(op_is_read(op));
+            self.blocking_run_it(|handle| unsafe { start_seq(handle, address, op, options) })?;
+        }
+        Ok(())
+    }
+}
+
+impl embedded_hal_1::i2c::Error for Error<I2cErrorFlags> {
+    fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        match self {
+            Error::HalError(error_flags) => error_flags.kind(),
+            _ => embedded_hal_1::i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl<M: Mode> embedded_hal_1::i2c::ErrorType for I2c<M> {
+    type Error = Error<I2cErrorFlags>;
+}
+
+impl<M: Mode> embedded_hal_1::i2c::I2c for I2c<M> {
+    fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        let result = self.blocking_read(address, read);
+        self.recover_on_error(result)
+    }
+
+    fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        let result = self.blocking_write(address, write);
+        self.recover_on_error(result)
+    }
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let result = self.blocking_write_read(address, write, read);
+        self.recover_on_error(result)
+    }
+
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let result = self.blocking_transaction(address, operations);
+        self.recover_on_error(result)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for I2c<Async> {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.read(address, read).await
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self.write(address, write).await
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write_read(address, write, read).await
+    }
+
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.transaction(address, operations).await
+    }
+}
@@ -0,0 +1,151 @@
+//! Bus scanning and device probing
+
+use super::*;
+
+/// Lowest and highest 7-bit addresses a device may use, the others are reserved.
+const FIRST_ADDRESS: u8 = 0x08;
+const LAST_ADDRESS: u8 = 0x77;
+
+/// How hard `probe` and `scan` try to reach a device.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProbeConfig {
+    /// Address attempts before a device counts as absent.
+    pub trials: u32,
+    /// Timeout of each attempt.
+    #[cfg(feature = "time")]
+    pub timeout: Duration,
+    #[cfg(not(feature = "time"))]
+    pub timeout_tick: u32,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            trials: 1,
+            #[cfg(feature = "time")]
+            timeout: Duration::from_millis(10),
+            #[cfg(not(feature = "time"))]
+            timeout_tick: 10,
+        }
+    }
+}
+
+impl ProbeConfig {
+    fn get_timeout_tick(&self) -> u32 {
+        #[cfg(feature = "time")]
+        let timout_tick = self.timeout.as_ticks() as u32;
+        #[cfg(not(feature = "time"))]
+        let timout_tick = self.timeout_tick;
+        timout_tick
+    }
+}
+
+impl<M: Mode> I2c<M> {
+    /// One `HAL_I2C_IsDeviceReady` attempt. A NACK is `Ok(false)`, not an error.
+    fn probe_once(&mut self, address: u8, trials: u32) -> Result<bool, Error<I2cErrorFlags>> {
+        let result = unsafe {
+            csdk::HAL_I2C_IsDeviceReady(
+                &mut self.handle,
+                (address as u16) << 1,
+                trials,
+                self.probe.get_timeout_tick(),
+            )
+        };
+        match result {
+            csdk::HAL_StatusTypeDef_HAL_ERROR => Ok(false),
+            _ => check(result, ||self.gerr()).map(|_| true),
+        }
+    }
+}
+
+impl I2c<Blocking> {
+    /// Check whether a device ACKs `address`.
+    pub fn probe(&mut self, address: u8) -> Result<bool, Error<I2cErrorFlags>> {
+        self.probe_once(address, self.probe.trials)
+    }
+
+    /// Walk the 7-bit address range (0x08..=0x77) and yield every address that ACKs.
+    pub fn scan(&mut self) -> Scan<'_> {
+        Scan {
+            i2c: self,
+            next: FIRST_ADDRESS,
+        }
+    }
+}
+
+/// Iterator returned by [`I2c::scan`].
+///
+/// Stops after the first error (e.g. `Error::Busy` on a stuck bus), which it yields.
+pub struct Scan<'a> {
+    i2c: &'a mut I2c<Blocking>,
+    next: u8,
+}
+
+impl Scan<'_> {
+    /// Scan the rest of the range, bit `n` of the result is set if address `n` ACKed.
+    pub fn bitmap(self) -> Result<u128, Error<I2cErrorFlags>> {
+        let mut bitmap = 0;
+        for address in self {
+            bitmap |= 1 << address?;
+        }
+        Ok(bitmap)
+    }
+}
+
+impl Iterator for Scan<'_> {
+    type Item = Result<u8, Error<I2cErrorFlags>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next <= LAST_ADDRESS {
+            let address = self.next;
+            self.next += 1;
+            match self.i2c.probe(address) {
+                Ok(true) => return Some(Ok(address)),
+                Ok(false) => (),
+                Err(e) => {
+                    self.next = LAST_ADDRESS + 1;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl I2c<Async> {
+    /// Check whether a device ACKs `address`, interrupt-driven.
+    pub async fn probe(&mut self, address: u8) -> Result<bool, Error<I2cErrorFlags>> {
+        for _ in 0..self.probe.trials.max(1) {
+            if self.probe_it(address).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// One address attempt through the IT path. A NACK is `Ok(false)`, not an error.
+    async fn probe_it(&mut self, address: u8) -> Result<bool, Error<I2cErrorFlags>> {
+        let start = |handle| unsafe { start_probe(handle, address) };
+        #[cfg(feature = "time")]
+        let result = self.run_it_inner(self.probe.timeout, start).await;
+        #[cfg(not(feature = "time"))]
+        let result = self.run_it_inner(start).await;
+        match self.recover_on_error(result) {
+            Ok(()) => Ok(true),
+            Err(Error::HalError(flags)) if flags.contains(I2cErrorFlags::NACK_ADDRESS) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Probe the 7-bit address range (0x08..=0x77), bit `n` of the result is set if address `n` ACKed.
+    pub async fn scan(&mut self) -> Result<u128, Error<I2cErrorFlags>> {
+        let mut bitmap = 0;
+        for address in FIRST_ADDRESS..=LAST_ADDRESS {
+            if self.probe(address).await? {
+                bitmap |= 1 << address;
+            }
+        }
+        Ok(bitmap)
+    }
+}
@@ -0,0 +1,190 @@
+//! Sharing one I2C peripheral between several device drivers
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+use super::*;
+
+/// Bus settings of one device on a shared bus.
+///
+/// Applied before each transaction of that device; the peripheral is only re-initialized
+/// if `init` differs from the settings in use.
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceConfig {
+    pub init: csdk::I2C_InitTypeDef,
+    /// Timeout.
+    #[cfg(feature = "time")]
+    pub timeout: Duration,
+    #[cfg(not(feature = "time"))]
+    pub timeout_tick: u32,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        let config = Config::default();
+        Self {
+            init: config.init,
+            #[cfg(feature = "time")]
+            timeout: config.timeout,
+            #[cfg(not(feature = "time"))]
+            timeout_tick: config.timeout_tick,
+        }
+    }
+}
+
+fn same_init(a: &csdk::I2C_InitTypeDef, b: &csdk::I2C_InitTypeDef) -> bool {
+    a.ClockSpeed == b.ClockSpeed
+        && a.DutyCycle == b.DutyCycle
+        && a.OwnAddress1 == b.OwnAddress1
+        && a.GeneralCallMode == b.GeneralCallMode
+        && a.NoStretchMode == b.NoStretchMode
+}
+
+impl<M: Mode> I2c<M> {
+    /// The settings in use.
+    fn device_config(&self) -> DeviceConfig {
+        DeviceConfig {
+            init: self.handle.Init,
+            #[cfg(feature = "time")]
+            timeout: self.timeout,
+            #[cfg(not(feature = "time"))]
+            timeout_tick: self.timeout_tick,
+        }
+    }
+
+    fn apply_device_config(&mut self, config: &DeviceConfig) -> Result<(), Error<I2cErrorFlags>> {
+        if !same_init(&self.handle.Init, &config.init) {
+            let previous = self.handle.Init;
+            self.handle.Init = config.init;
+            if let Err(e) = unsafe { check(csdk::HAL_I2C_Init(&mut self.handle), ||self.gerr()) } {
+                // keep the handle in line with the hardware, which may be half way re-initialized
+                self.handle.Init = previous;
+                unsafe { csdk::HAL_I2C_Init(&mut self.handle) };
+                return Err(e);
+            }
+        }
+        #[cfg(feature = "time")]
+        {
+            self.timeout = config.timeout;
+        }
+        #[cfg(not(feature = "time"))]
+        {
+            self.timeout_tick = config.timeout_tick;
+        }
+        Ok(())
+    }
+}
+
+/// An I2C bus shared by blocking device drivers.
+///
+/// The bus is claimed inside a critical section, but transfers run outside of it, so the HAL
+/// tick (and with it the timeouts) keeps running. A device that finds the bus claimed, e.g.
+/// from an interrupt, gets `Error::Busy`.
+pub struct BlockingSharedBus<M: Mode> {
+    bus: SharedBusCell<I2c<M>>,
+    /// The settings the bus was created with.
+    default: DeviceConfig,
+}
+
+// The driver owns its peripheral, and a claim hands it out to one context at a time.
+unsafe impl<M: Mode> Sync for SharedBusCell<I2c<M>> {}
+
+impl<M: Mode> BlockingSharedBus<M> {
+    pub fn new(i2c: I2c<M>) -> Self {
+        let default = i2c.device_config();
+        Self { bus: SharedBusCell::new(i2c), default }
+    }
+
+    /// A device using the bus settings the bus was created with.
+    pub fn device(&self) -> BlockingI2cDevice<'_, M> {
+        BlockingI2cDevice { bus: self, config: self.default }
+    }
+
+    /// A device with its own bus settings.
+    pub fn device_with_config(&self, config: DeviceConfig) -> BlockingI2cDevice<'_, M> {
+        BlockingI2cDevice { bus: self, config }
+    }
+}
+
+/// One device on a [`BlockingSharedBus`].
+pub struct BlockingI2cDevice<'a, M: Mode> {
+    bus: &'a BlockingSharedBus<M>,
+    config: DeviceConfig,
+}
+
+impl<M: Mode> BlockingI2cDevice<'_, M> {
+    fn lock(&mut self) -> Result<SharedBusClaim<'_, I2c<M>>, Error<I2cErrorFlags>> {
+        let mut bus = self.bus.bus.claim().ok_or(Error::Busy)?;
+        bus.apply_device_config(&self.config)?;
+        Ok(bus)
+    }
+}
+
+impl<M: Mode> embedded_hal_1::i2c::ErrorType for BlockingI2cDevice<'_, M> {
+    type Error = Error<I2cErrorFlags>;
+}
+
+impl<M: Mode> embedded_hal_1::i2c::I2c for BlockingI2cDevice<'_, M> {
+    fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        embedded_hal_1::i2c::I2c::write_read(&mut *self.lock()?, address, write, read)
+    }
+
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        embedded_hal_1::i2c::I2c::transaction(&mut *self.lock()?, address, operations)
+    }
+}
+
+/// An I2C bus shared by async device drivers, claimed through an `embassy_sync` mutex.
+pub struct SharedBus {
+    bus: Mutex<CriticalSectionRawMutex, I2c<Async>>,
+    /// The settings the bus was created with.
+    default: DeviceConfig,
+}
+
+impl SharedBus {
+    pub fn new(i2c: I2c<Async>) -> Self {
+        let default = i2c.device_config();
+        Self { bus: Mutex::new(i2c), default }
+    }
+
+    /// A device using the bus settings the bus was created with.
+    pub fn device(&self) -> I2cDevice<'_> {
+        I2cDevice { bus: self, config: self.default }
+    }
+
+    /// A device with its own bus settings.
+    pub fn device_with_config(&self, config: DeviceConfig) -> I2cDevice<'_> {
+        I2cDevice { bus: self, config }
+    }
+}
+
+/// One device on a [`SharedBus`].
+pub struct I2cDevice<'a> {
+    bus: &'a SharedBus,
+    config: DeviceConfig,
+}
+
+impl embedded_hal_1::i2c::ErrorType for I2cDevice<'_> {
+    type Error = Error<I2cErrorFlags>;
+}
+
+impl embedded_hal_async::i2c::I2c for I2cDevice<'_> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut bus = self.bus.bus.lock().await;
+        bus.apply_device_config(&self.config)?;
+        bus.transaction(address, operations).await
+    }
+}
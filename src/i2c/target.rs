@@ -0,0 +1,241 @@
+//! I2C target (slave) mode
+
+use core::future::poll_fn;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::sync::atomic::Ordering;
+use core::task::Poll;
+
+use super::*;
+
+pub(super) const NO_COMMAND: u8 = 0;
+const COMMAND_WRITE: u8 = 1;
+const COMMAND_READ: u8 = 2;
+const COMMAND_GENERAL_CALL: u8 = 3;
+
+/// What the controller asked for after addressing the target.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// The controller writes to us, serve it with [`I2cTarget::respond_to_write`].
+    Write,
+    /// The controller reads from us, serve it with [`I2cTarget::respond_to_read`].
+    Read,
+    /// The controller writes to the general call address (0x00).
+    /// Only seen if `GeneralCallMode` is enabled in the config.
+    GeneralCall,
+}
+
+/// Interrupt-driven I2C target.
+///
+/// The target answers on `Config::init.OwnAddress1`. Listening stays enabled between calls and
+/// the interrupt handler keeps using the handle, so the target is served through
+/// `Pin<&mut Self>`, e.g. after `core::pin::pin!`.
+pub struct I2cTarget {
+    pub handle: csdk::I2C_HandleTypeDef,
+    /// Timeout of a frame once it is armed.
+    #[cfg(feature = "time")]
+    pub timeout: Duration,
+    _pinned: PhantomPinned,
+}
+
+impl I2cTarget {
+    /// Create a new I2C target.
+    pub fn new_from_csdk(instance: *mut csdk::I2C_TypeDef, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let i2c = I2c::<Async>::new_inner(instance, config)?;
+        enable_irq(i2c.handle.Instance);
+        Ok(Self {
+            handle: i2c.handle,
+            #[cfg(feature = "time")]
+            timeout: i2c.timeout,
+            _pinned: PhantomPinned,
+        })
+    }
+
+    #[cfg(feature = "peri-i2c0")]
+    pub fn new(config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let instance = csdk::I2C;
+        Self::new_from_csdk(instance, config)
+    }
+
+    #[cfg(not(feature = "peri-i2c0"))]
+    pub fn new(instance_num: u8, config: Config) -> Result<Self, Error<I2cErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, config)
+    }
+
+    fn gerr(&self) -> Error<I2cErrorFlags> {
+        Error::HalError(I2cErrorFlags::from_bits_truncate(self.handle.ErrorCode))
+    }
+
+    fn state(&self) -> u32 {
+        unsafe { core::ptr::read_volatile(&self.handle.State) }
+    }
+
+    fn register(&mut self) -> usize {
+        let index = state_index(self.handle.Instance);
+        STATES[index].handle.store(&mut self.handle, Ordering::Relaxed);
+        index
+    }
+
+    /// Wait until the controller addresses us.
+    ///
+    /// The bus is held (clock stretched) until the command is served with
+    /// [`respond_to_read`](Self::respond_to_read) or [`respond_to_write`](Self::respond_to_write).
+    pub async fn listen(self: Pin<&mut Self>) -> Result<Command, Error<I2cErrorFlags>> {
+        // the target is only used in place
+        let this = unsafe { self.get_unchecked_mut() };
+        this.listen_inner().await
+    }
+
+    async fn listen_inner(&mut self) -> Result<Command, Error<I2cErrorFlags>> {
+        let index = self.register();
+
+        if self.state() == csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_READY {
+            self.handle.ErrorCode = csdk::HAL_I2C_ERROR_NONE;
+            STATES[index].command.store(NO_COMMAND, Ordering::Relaxed);
+            unsafe {
+                check(csdk::HAL_I2C_EnableListen_IT(&mut self.handle), ||self.gerr())?;
+            }
+        }
+
+        poll_fn(|cx| {
+            STATES[index].waker.register(cx.waker());
+            let command = match STATES[index].command.load(Ordering::Relaxed) {
+                COMMAND_WRITE => Command::Write,
+                COMMAND_READ => Command::Read,
+                COMMAND_GENERAL_CALL => Command::GeneralCall,
+                _ => {
+                    // listening ends with the state back to ready only on errors
+                    if self.state() == csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_READY {
+                        return Poll::Ready(Err(self.gerr()));
+                    }
+                    return Poll::Pending;
+                }
+            };
+            STATES[index].command.store(NO_COMMAND, Ordering::Relaxed);
+            Poll::Ready(Ok(command))
+        }).await
+    }
+
+    /// Serve a [`Command::Read`] with `write`.
+    ///
+    /// Returns the number of bytes the controller clocked out before it NACKed.
+    pub async fn respond_to_read(self: Pin<&mut Self>, write: &[u8]) -> Result<usize, Error<I2cErrorFlags>> {
+        // the target is only used in place
+        let this = unsafe { self.get_unchecked_mut() };
+        this.run_frame(|handle| unsafe {
+            csdk::HAL_I2C_Slave_Seq_Transmit_IT(
+                handle,
+                write.as_ptr() as *mut u8,
+                write.len() as u16,
+                csdk::I2C_LAST_FRAME,
+            )
+        }).await?;
+        // the controller NACKing the last byte is how a read ends
+        this.finish(write.len(), I2cErrorFlags::NACK)
+    }
+
+    /// Serve a [`Command::Write`] or [`Command::GeneralCall`] into `read`.
+    ///
+    /// Returns the number of bytes received before the STOP. `read` should be large enough
+    /// for the longest write, the controller is stretched once it is full.
+    pub async fn respond_to_write(self: Pin<&mut Self>, read: &mut [u8]) -> Result<usize, Error<I2cErrorFlags>> {
+        // the target is only used in place
+        let this = unsafe { self.get_unchecked_mut() };
+        let len = read.len();
+        this.run_frame(|handle| unsafe {
+            csdk::HAL_I2C_Slave_Seq_Receive_IT(handle, read.as_mut_ptr(), len as u16, csdk::I2C_LAST_FRAME)
+        }).await?;
+        this.finish(len, I2cErrorFlags::empty())
+    }
+
+    /// Arm a frame and wait until the CSDK leaves the busy-listen states.
+    ///
+    /// If the returned future is dropped (or times out) before the frame is done, the
+    /// peripheral is reset, which also ends listening.
+    async fn run_frame<F>(&mut self, start: F) -> Result<(), Error<I2cErrorFlags>>
+    where
+        F: FnOnce(*mut csdk::I2C_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+    {
+        let index = self.register();
+        let handle = &mut self.handle as *mut csdk::I2C_HandleTypeDef;
+        check(start(handle), || self.gerr())?;
+
+        let on_drop = OnDrop::new(|| unsafe {
+            abort_it(handle);
+            STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        });
+
+        let wait = poll_fn(|cx| {
+            STATES[index].waker.register(cx.waker());
+            match unsafe { core::ptr::read_volatile(&(*handle).State) } {
+                csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_LISTEN
+                | csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_READY => Poll::Ready(()),
+                _ => Poll::Pending,
+            }
+        });
+
+        #[cfg(feature = "time")]
+        if embassy_time::with_timeout(self.timeout, wait).await.is_err() {
+            drop(on_drop);
+            return Err(Error::Timeout);
+        }
+        #[cfg(not(feature = "time"))]
+        wait.await;
+
+        on_drop.defuse();
+        Ok(())
+    }
+
+    fn finish(&mut self, len: usize, expected: I2cErrorFlags) -> Result<usize, Error<I2cErrorFlags>> {
+        let remaining = unsafe { core::ptr::read_volatile(&self.handle.XferCount) } as usize;
+        if self.state() == csdk::HAL_I2C_StateTypeDef_HAL_I2C_STATE_READY {
+            // listening is over, nothing will touch the handle until the next `listen`
+            let index = state_index(self.handle.Instance);
+            STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        }
+
+        let mut flags = I2cErrorFlags::from_bits_truncate(self.handle.ErrorCode);
+        flags.remove(expected);
+        if !flags.is_empty() {
+            return Err(Error::HalError(flags));
+        }
+        Ok(len - remaining)
+    }
+}
+
+impl Drop for I2cTarget {
+    fn drop(&mut self) {
+        unsafe {
+            csdk::HAL_I2C_DeInit(&mut self.handle);
+        }
+        let index = state_index(self.handle.Instance);
+        STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+    }
+}
+
+/// Overrides the weak CSDK callback, called from `HAL_I2C_EV_IRQHandler` on an address match.
+#[no_mangle]
+unsafe extern "C" fn HAL_I2C_AddrCallback(
+    hi2c: *mut csdk::I2C_HandleTypeDef,
+    transfer_direction: u8,
+    _addr_match_code: u16,
+) {
+    let instance = (*hi2c).Instance;
+    // ADDR stays set (and the bus stretched) until the transfer is armed,
+    // mask the event interrupt so it does not fire again in the meantime.
+    // The `Slave_Seq_*_IT` calls re-enable it.
+    (*instance).CR2 &= !csdk::I2C_CR2_ITEVTEN;
+
+    let command = if transfer_direction as u32 == csdk::I2C_DIRECTION_RECEIVE {
+        // the controller receives, we transmit
+        COMMAND_READ
+    } else if (*instance).SR2 & csdk::I2C_SR2_GENCALL != 0 {
+        COMMAND_GENERAL_CALL
+    } else {
+        COMMAND_WRITE
+    };
+    let index = state_index(instance);
+    STATES[index].command.store(command, Ordering::Relaxed);
+}
@@ -2,6 +2,7 @@
 #![no_std]
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<E> {
     HalError(E),
     Busy,
@@ -10,11 +11,23 @@ pub enum Error<E> {
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum InputError {
     InvalidInstance,
+    /// The operation needs a pin that was not given to the driver.
+    MissingPin,
+    /// The operation needs a DMA channel that was not linked to the driver.
+    MissingDma,
+    /// A raw DMA request code that maps to no request.
+    InvalidDmaRequest,
+    /// A buffer is empty or longer than the driver can handle.
+    InvalidBufferLength,
+    /// The config asks for something the driver does not support.
+    UnsupportedConfig,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum InputErrorType {
     Instant,
 }
@@ -33,6 +46,7 @@ pub fn init(){
 }
 
 
+#[derive(Copy, Clone)]
 pub struct Timeout {
     #[cfg(feature = "time")]
     pub timeout: embassy_time::Duration,
@@ -61,6 +75,79 @@ impl Timeout {
     }
 }
 
+/// Runs a closure when dropped, unless defused.
+///
+/// Used by async drivers to abort an in-flight transfer if its future is dropped.
+#[must_use = "to delay the drop handler invocation to the end of the scope"]
+pub(crate) struct OnDrop<F: FnOnce()> {
+    f: core::mem::MaybeUninit<F>,
+}
+
+impl<F: FnOnce()> OnDrop<F> {
+    pub(crate) fn new(f: F) -> Self {
+        Self { f: core::mem::MaybeUninit::new(f) }
+    }
+
+    pub(crate) fn defuse(self) {
+        core::mem::forget(self)
+    }
+}
+
+impl<F: FnOnce()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        unsafe { self.f.as_ptr().read()() }
+    }
+}
+
+/// A bus used by one blocking device driver at a time.
+///
+/// The bus is claimed and released inside a critical section, but transfers run outside of
+/// it, so the HAL tick (and with it the timeouts) keeps running.
+pub(crate) struct SharedBusCell<T> {
+    bus: core::cell::RefCell<T>,
+}
+
+// The `RefCell` borrow flag is only changed inside a critical section, by `claim` and by
+// dropping the `SharedBusClaim`. `Sync` is implemented next to the shared bus types, only for
+// the bus drivers, which are not `Send` because of the raw pointers in their CSDK handles.
+
+impl<T> SharedBusCell<T> {
+    pub(crate) const fn new(bus: T) -> Self {
+        Self { bus: core::cell::RefCell::new(bus) }
+    }
+
+    /// Claim the bus, `None` if it is claimed already, e.g. by the code an interrupt preempted.
+    pub(crate) fn claim(&self) -> Option<SharedBusClaim<'_, T>> {
+        critical_section::with(|_| self.bus.try_borrow_mut().ok())
+            .map(|bus| SharedBusClaim { bus: core::mem::ManuallyDrop::new(bus) })
+    }
+}
+
+/// Access to a claimed [`SharedBusCell`], releases it when dropped.
+pub(crate) struct SharedBusClaim<'a, T> {
+    bus: core::mem::ManuallyDrop<core::cell::RefMut<'a, T>>,
+}
+
+impl<T> core::ops::Deref for SharedBusClaim<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.bus
+    }
+}
+
+impl<T> core::ops::DerefMut for SharedBusClaim<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.bus
+    }
+}
+
+impl<T> Drop for SharedBusClaim<'_, T> {
+    fn drop(&mut self) {
+        critical_section::with(|_| unsafe { core::mem::ManuallyDrop::drop(&mut self.bus) });
+    }
+}
+
 pub mod mode {
     trait SealedMode {}
 
@@ -96,6 +183,9 @@ pub mod i2c;
 
 pub mod uart;
 
+#[cfg(feature = "peri-spi")]
+pub mod spi;
+
 pub mod exti;
 
 pub mod rcc;
@@ -0,0 +1,280 @@
+//! SPI devices with a managed chip select, on an exclusive or a shared bus
+
+#[cfg(feature = "peri-dma")]
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+#[cfg(feature = "peri-dma")]
+use embassy_sync::mutex::Mutex;
+use embedded_hal_1::spi::Operation;
+
+use super::*;
+
+/// Settings of one device on the bus.
+#[derive(Copy, Clone)]
+pub struct DeviceConfig {
+    /// Bus settings applied before each transaction of this device. `None` uses the settings a
+    /// shared bus was created with, or keeps the settings in use on a bus of its own. The
+    /// peripheral is only re-initialized if they differ.
+    pub spi: Option<Config>,
+    /// Time from CS going low to the first clock edge, in µs.
+    pub cs_setup_us: u32,
+    /// Time from the last clock edge to CS going high, in µs.
+    pub cs_hold_us: u32,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            spi: None,
+            cs_setup_us: 0,
+            cs_hold_us: 0,
+        }
+    }
+}
+
+/// Bus settings recorded when a shared bus is created.
+#[derive(Copy, Clone)]
+struct BusSettings {
+    init: csdk::SPI_InitTypeDef,
+    timeout: Timeout,
+}
+
+impl<M: Mode> Spi<M> {
+    fn bus_settings(&self) -> BusSettings {
+        BusSettings {
+            init: self.handle.Init,
+            timeout: self.timeout,
+        }
+    }
+
+    /// Apply the bus settings of a device, or `default` if it has none, keeping the word size
+    /// in use.
+    fn apply_device_config(&mut self, config: &DeviceConfig, default: Option<&BusSettings>) -> Result<(), Error<SpiErrorFlags>> {
+        let settings = match (&config.spi, default) {
+            (Some(config), _) => BusSettings { init: config.init(), timeout: config.timeout },
+            (None, Some(default)) => *default,
+            (None, None) => return Ok(()),
+        };
+        self.timeout = settings.timeout;
+        let mut init = settings.init;
+        init.DataSize = self.handle.Init.DataSize;
+        let current = &self.handle.Init;
+        if current.CLKPolarity == init.CLKPolarity
+            && current.CLKPhase == init.CLKPhase
+            && current.BaudRatePrescaler == init.BaudRatePrescaler
+            && current.FirstBit == init.FirstBit
+        {
+            return Ok(());
+        }
+        self.reinit(init)
+    }
+}
+
+/// Chip select pin with its timing.
+struct ChipSelect {
+    pin: gpio::AnyPin,
+    config: DeviceConfig,
+}
+
+impl ChipSelect {
+    fn new(mut pin: gpio::AnyPin, config: DeviceConfig) -> Self {
+        pin.set_high();
+        pin.set_as_output(gpio::Speed::VeryHigh);
+        Self { pin, config }
+    }
+
+    fn select(&mut self) {
+        self.pin.set_low();
+        if self.config.cs_setup_us > 0 {
+            csdk_hal::delay_us(self.config.cs_setup_us);
+        }
+    }
+
+    fn deselect(&mut self) {
+        if self.config.cs_hold_us > 0 {
+            csdk_hal::delay_us(self.config.cs_hold_us);
+        }
+        self.pin.set_high();
+    }
+
+    /// Select the device, run the operations and deselect it again, even if one failed.
+    fn blocking_transaction<M: Mode, W: Word>(
+        &mut self,
+        bus: &mut Spi<M>,
+        default: Option<&BusSettings>,
+        operations: &mut [Operation<'_, W>],
+    ) -> Result<(), Error<SpiErrorFlags>> {
+        bus.apply_device_config(&self.config, default)?;
+        self.select();
+        let result = operations.iter_mut().try_for_each(|op| match op {
+            Operation::Read(words) => bus.blocking_read(words),
+            Operation::Write(words) => bus.blocking_write(words),
+            Operation::Transfer(read, write) => bus.blocking_transfer(read, write),
+            Operation::TransferInPlace(words) => bus.blocking_transfer_in_place(words),
+            Operation::DelayNs(ns) => {
+                csdk_hal::delay_us(ns.div_ceil(1000));
+                Ok(())
+            },
+        });
+        self.deselect();
+        result
+    }
+
+    #[cfg(feature = "peri-dma")]
+    async fn transaction<W: Word>(
+        &mut self,
+        bus: &mut Spi<Async>,
+        default: Option<&BusSettings>,
+        operations: &mut [Operation<'_, W>],
+    ) -> Result<(), Error<SpiErrorFlags>> {
+        bus.apply_device_config(&self.config, default)?;
+        self.select();
+        // a dropped future aborts the transfer, CS must not stay low
+        let pin = &mut self.pin as *mut gpio::AnyPin;
+        let on_drop = OnDrop::new(|| unsafe { (*pin).set_high() });
+        let mut result = Ok(());
+        for op in operations.iter_mut() {
+            result = match op {
+                Operation::Read(words) => bus.read(words).await,
+                Operation::Write(words) => bus.write(words).await,
+                Operation::Transfer(read, write) => bus.transfer(read, write).await,
+                Operation::TransferInPlace(words) => bus.transfer_in_place(words).await,
+                Operation::DelayNs(ns) => {
+                    #[cfg(feature = "time")]
+                    embassy_time::Timer::after_nanos(*ns as u64).await;
+                    #[cfg(not(feature = "time"))]
+                    csdk_hal::delay_us(ns.div_ceil(1000));
+                    Ok(())
+                },
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        on_drop.defuse();
+        self.deselect();
+        result
+    }
+}
+
+/// A device with a bus of its own.
+pub struct SpiDevice<M: Mode> {
+    bus: Spi<M>,
+    cs: ChipSelect,
+}
+
+impl<M: Mode> SpiDevice<M> {
+    /// `cs` is taken over as a push-pull output, high while the device is not selected.
+    pub fn new(bus: Spi<M>, cs: gpio::AnyPin, config: DeviceConfig) -> Self {
+        Self { bus, cs: ChipSelect::new(cs, config) }
+    }
+
+    /// Give back the bus and the chip select pin.
+    pub fn release(self) -> (Spi<M>, gpio::AnyPin) {
+        (self.bus, self.cs.pin)
+    }
+}
+
+impl<M: Mode> embedded_hal_1::spi::ErrorType for SpiDevice<M> {
+    type Error = Error<SpiErrorFlags>;
+}
+
+impl<M: Mode, W: Word> embedded_hal_1::spi::SpiDevice<W> for SpiDevice<M> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        self.cs.blocking_transaction(&mut self.bus, None, operations)
+    }
+}
+
+#[cfg(feature = "peri-dma")]
+impl<W: Word> embedded_hal_async::spi::SpiDevice<W> for SpiDevice<Async> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        self.cs.transaction(&mut self.bus, None, operations).await
+    }
+}
+
+/// An SPI bus shared by blocking device drivers.
+///
+/// The bus is claimed inside a critical section, but transfers run outside of it, so the HAL
+/// tick (and with it the timeouts) keeps running. A device that finds the bus claimed, e.g.
+/// from an interrupt, gets `Error::Busy`.
+pub struct BlockingSharedBus<M: Mode> {
+    bus: SharedBusCell<Spi<M>>,
+    /// The settings the bus was created with.
+    default: BusSettings,
+}
+
+// The driver owns its peripheral, and a claim hands it out to one context at a time.
+unsafe impl<M: Mode> Sync for SharedBusCell<Spi<M>> {}
+
+impl<M: Mode> BlockingSharedBus<M> {
+    pub fn new(spi: Spi<M>) -> Self {
+        let default = spi.bus_settings();
+        Self { bus: SharedBusCell::new(spi), default }
+    }
+
+    /// A device selected by `cs`, which is taken over as a push-pull output.
+    pub fn device(&self, cs: gpio::AnyPin, config: DeviceConfig) -> BlockingSharedSpiDevice<'_, M> {
+        BlockingSharedSpiDevice { bus: self, cs: ChipSelect::new(cs, config) }
+    }
+
+    fn lock(&self) -> Result<SharedBusClaim<'_, Spi<M>>, Error<SpiErrorFlags>> {
+        self.bus.claim().ok_or(Error::Busy)
+    }
+}
+
+/// One device on a [`BlockingSharedBus`].
+pub struct BlockingSharedSpiDevice<'a, M: Mode> {
+    bus: &'a BlockingSharedBus<M>,
+    cs: ChipSelect,
+}
+
+impl<M: Mode> embedded_hal_1::spi::ErrorType for BlockingSharedSpiDevice<'_, M> {
+    type Error = Error<SpiErrorFlags>;
+}
+
+impl<M: Mode, W: Word> embedded_hal_1::spi::SpiDevice<W> for BlockingSharedSpiDevice<'_, M> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        let mut bus = self.bus.lock()?;
+        self.cs.blocking_transaction(&mut bus, Some(&self.bus.default), operations)
+    }
+}
+
+/// An SPI bus shared by async device drivers, claimed through an `embassy_sync` mutex.
+#[cfg(feature = "peri-dma")]
+pub struct SharedBus {
+    bus: Mutex<CriticalSectionRawMutex, Spi<Async>>,
+    /// The settings the bus was created with.
+    default: BusSettings,
+}
+
+#[cfg(feature = "peri-dma")]
+impl SharedBus {
+    pub fn new(spi: Spi<Async>) -> Self {
+        let default = spi.bus_settings();
+        Self { bus: Mutex::new(spi), default }
+    }
+
+    /// A device selected by `cs`, which is taken over as a push-pull output.
+    pub fn device(&self, cs: gpio::AnyPin, config: DeviceConfig) -> SharedSpiDevice<'_> {
+        SharedSpiDevice { bus: self, cs: ChipSelect::new(cs, config) }
+    }
+}
+
+/// One device on a [`SharedBus`].
+#[cfg(feature = "peri-dma")]
+pub struct SharedSpiDevice<'a> {
+    bus: &'a SharedBus,
+    cs: ChipSelect,
+}
+
+#[cfg(feature = "peri-dma")]
+impl embedded_hal_1::spi::ErrorType for SharedSpiDevice<'_> {
+    type Error = Error<SpiErrorFlags>;
+}
+
+#[cfg(feature = "peri-dma")]
+impl<W: Word> embedded_hal_async::spi::SpiDevice<W> for SharedSpiDevice<'_> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        let mut bus = self.bus.bus.lock().await;
+        self.cs.transaction(&mut bus, Some(&self.bus.default), operations).await
+    }
+}
@@ -0,0 +1,273 @@
+//! SPI transfers through linked DMA channels
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use super::*;
+
+/// Where the words of one side of a DMA transfer come from or go to.
+enum DmaBuffer<W> {
+    /// A buffer of at least the transfer length.
+    Buffer(*mut W),
+    /// The same word over and over, for the side nobody cares about.
+    Dummy(*mut W),
+}
+
+impl<W> DmaBuffer<W> {
+    fn ptr(&self) -> *mut W {
+        match self {
+            Self::Buffer(ptr) | Self::Dummy(ptr) => *ptr,
+        }
+    }
+
+    fn mem_inc(&self) -> bool {
+        matches!(self, Self::Buffer(_))
+    }
+
+    /// The rest of the buffer after `len` words.
+    fn advance(&mut self, len: usize) {
+        if let Self::Buffer(ptr) = self {
+            *ptr = unsafe { ptr.add(len) };
+        }
+    }
+}
+
+/// The DMA request of the TX or the RX side of `instance`.
+pub(super) fn dma_request(instance: *mut csdk::SPI_TypeDef, tx: bool) -> dma::DmaRequest {
+    match (state_index(instance), tx) {
+        (0, true) => dma::DmaRequest::Spi1Tx,
+        (0, false) => dma::DmaRequest::Spi1Rx,
+        (_, true) => dma::DmaRequest::Spi2Tx,
+        (_, false) => dma::DmaRequest::Spi2Rx,
+    }
+}
+
+pub(super) fn dma_error(error: Error<dma::DmaErrorFlags>) -> Error<SpiErrorFlags> {
+    match error {
+        Error::HalError(_) => Error::HalError(SpiErrorFlags::DMA),
+        Error::Busy => Error::Busy,
+        Error::Timeout => Error::Timeout,
+        Error::UserInput(e) => Error::UserInput(e),
+    }
+}
+
+impl Spi<Async> {
+    /// Run a full-duplex DMA transfer of `len` words, in chunks of the 16-bit DMA count.
+    async fn run_dma<W: Word>(
+        &mut self,
+        mut read: DmaBuffer<W>,
+        mut write: DmaBuffer<W>,
+        len: usize,
+    ) -> Result<(), Error<SpiErrorFlags>> {
+        if len == 0 {
+            return Ok(());
+        }
+        let hdmatx = self.handle.hdmatx;
+        let hdmarx = self.handle.hdmarx;
+        if hdmatx.is_null() || hdmarx.is_null() {
+            return Err(Error::UserInput(InputError::MissingDma));
+        }
+        self.set_word_size::<W>()?;
+
+        let half_word = W::DATA_SIZE == csdk::SPI_DATASIZE_16BIT;
+        unsafe {
+            dma::set_buffer_mode(hdmatx, half_word, write.mem_inc()).map_err(dma_error)?;
+            dma::set_buffer_mode(hdmarx, half_word, read.mem_inc()).map_err(dma_error)?;
+            // the channels may have been linked before the driver was moved
+            let parent = &mut self.handle as *mut csdk::SPI_HandleTypeDef as *mut core::ffi::c_void;
+            (*hdmatx).Parent = parent;
+            (*hdmarx).Parent = parent;
+            dma::enable_irq(hdmatx);
+            dma::enable_irq(hdmarx);
+        }
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = remaining.min(u16::MAX as usize);
+            let (tx, rx) = (write.ptr(), read.ptr());
+            self.run_it(|handle| unsafe {
+                csdk::HAL_SPI_TransmitReceive_DMA(handle, tx as *mut u8, rx as *mut u8, chunk as u16)
+            }).await?;
+            write.advance(chunk);
+            read.advance(chunk);
+            remaining -= chunk;
+        }
+        Ok(())
+    }
+
+    /// Register the handle with the interrupt handler, start a transfer and wait for it.
+    ///
+    /// If the returned future is dropped (or times out) before the transfer is done,
+    /// the transfer is aborted.
+    async fn run_it<F>(&mut self, start: F) -> Result<(), Error<SpiErrorFlags>>
+    where
+        F: FnOnce(*mut csdk::SPI_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+    {
+        let index = state_index(self.handle.Instance);
+        let handle = &mut self.handle as *mut csdk::SPI_HandleTypeDef;
+        STATES[index].handle.store(handle, Ordering::Relaxed);
+
+        if let Err(e) = check(start(handle), || self.gerr()) {
+            STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+            return Err(e);
+        }
+
+        let on_drop = OnDrop::new(|| unsafe {
+            csdk::HAL_SPI_Abort(handle);
+            STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        });
+
+        let wait = poll_fn(|cx| {
+            STATES[index].waker.register(cx.waker());
+            let state = unsafe { core::ptr::read_volatile(&(*handle).State) };
+            if state == csdk::HAL_SPI_StateTypeDef_HAL_SPI_STATE_READY {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+
+        #[cfg(feature = "time")]
+        if embassy_time::with_timeout(self.timeout.timeout, wait).await.is_err() {
+            drop(on_drop);
+            return Err(Error::Timeout);
+        }
+        #[cfg(not(feature = "time"))]
+        wait.await;
+
+        on_drop.defuse();
+        STATES[index].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+
+        let error_code = unsafe { core::ptr::read_volatile(&(*handle).ErrorCode) };
+        if error_code != csdk::HAL_SPI_ERROR_NONE {
+            return Err(self.gerr());
+        }
+        Ok(())
+    }
+
+    /// Write words through the linked DMA channels, what is read back is dropped.
+    pub async fn write<W: Word>(&mut self, words: &[W]) -> Result<(), Error<SpiErrorFlags>> {
+        let mut dummy = W::default();
+        self.run_dma(
+            DmaBuffer::Dummy(&mut dummy),
+            DmaBuffer::Buffer(words.as_ptr() as *mut W),
+            words.len(),
+        ).await
+    }
+
+    /// Read words through the linked DMA channels, zeros are written meanwhile.
+    pub async fn read<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error<SpiErrorFlags>> {
+        let mut dummy = W::default();
+        let len = words.len();
+        self.run_dma(
+            DmaBuffer::Buffer(words.as_mut_ptr()),
+            DmaBuffer::Dummy(&mut dummy),
+            len,
+        ).await
+    }
+
+    /// Write and read at the same time, see [`embedded_hal_1::spi::SpiBus::transfer`]
+    /// for buffers of different lengths.
+    pub async fn transfer<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<(), Error<SpiErrorFlags>> {
+        let common = read.len().min(write.len());
+        let (read, read_rest) = read.split_at_mut(common);
+        let (write, write_rest) = write.split_at(common);
+        self.run_dma(
+            DmaBuffer::Buffer(read.as_mut_ptr()),
+            DmaBuffer::Buffer(write.as_ptr() as *mut W),
+            common,
+        ).await?;
+        self.write(write_rest).await?;
+        self.read(read_rest).await
+    }
+
+    /// Write `words` and replace them with the words read at the same time.
+    pub async fn transfer_in_place<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error<SpiErrorFlags>> {
+        // the TX channel always fetches a word before the RX channel stores the word received with it
+        let ptr = words.as_mut_ptr();
+        self.run_dma(DmaBuffer::Buffer(ptr), DmaBuffer::Buffer(ptr), words.len()).await
+    }
+}
+
+// Transfers end in the DMA interrupt, wake from the CSDK callbacks.
+
+unsafe fn wake(hspi: *mut csdk::SPI_HandleTypeDef) {
+    STATES[state_index((*hspi).Instance)].waker.wake();
+}
+
+#[no_mangle]
+unsafe extern "C" fn HAL_SPI_TxRxCpltCallback(hspi: *mut csdk::SPI_HandleTypeDef) {
+    wake(hspi);
+}
+
+#[no_mangle]
+unsafe extern "C" fn HAL_SPI_ErrorCallback(hspi: *mut csdk::SPI_HandleTypeDef) {
+    wake(hspi);
+}
+
+impl embedded_hal_async::spi::SpiBus<u8> for Spi<Async> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.read(words).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.write(words).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.transfer(read, write).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.transfer_in_place(words).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // transfers only complete once the bus is idle
+        Ok(())
+    }
+}
+
+impl embedded_hal_async::spi::SpiBus<u16> for Spi<Async> {
+    async fn read(&mut self, words: &mut [u16]) -> Result<(), Self::Error> {
+        self.read(words).await
+    }
+
+    async fn write(&mut self, words: &[u16]) -> Result<(), Self::Error> {
+        self.write(words).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u16], write: &[u16]) -> Result<(), Self::Error> {
+        self.transfer(read, write).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u16]) -> Result<(), Self::Error> {
+        self.transfer_in_place(words).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // transfers only complete once the bus is idle
+        Ok(())
+    }
+}
+
+impl<M: Mode> dma::HasDmaField for Spi<M> {
+    /// A memory-to-peripheral channel becomes the TX channel, anything else the RX channel.
+    ///
+    /// `Parent` is pointed at the handle again before each transfer.
+    fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel) {
+        let tx = dma_handle.handle.Init.Direction == csdk::DMA_MEMORY_TO_PERIPH;
+        dma_handle.set_request(dma_request(self.handle.Instance, tx));
+        if tx {
+            self.handle.hdmatx = &mut dma_handle.handle;
+        } else {
+            self.handle.hdmarx = &mut dma_handle.handle;
+        }
+    }
+
+    fn get_handle_ptr(&mut self) -> *mut core::ffi::c_void {
+        &mut self.handle
+            as *mut csdk::SPI_HandleTypeDef
+            as *mut core::ffi::c_void
+    }
+}
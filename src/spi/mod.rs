@@ -0,0 +1,453 @@
+//! Serial Peripheral Interface (SPI)
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use embedded_hal as embedded_hal_1;
+use embedded_hal_1::spi::{Phase, Polarity};
+use embassy_sync::waitqueue::AtomicWaker;
+use defmt::bitflags;
+
+use csdk_hal::check;
+use crate::*;
+use crate::csdk::interrupts::interrupt;
+use crate::mode::{Async, Blocking, Mode};
+
+mod device;
+pub use device::*;
+#[cfg(feature = "peri-dma")]
+mod dma_transfer;
+#[cfg(feature = "peri-dma")]
+mod slave;
+#[cfg(feature = "peri-dma")]
+pub use slave::*;
+
+/// Per-instance interrupt state.
+///
+/// `handle` points at the driver's `SPI_HandleTypeDef` only while a DMA transfer is running.
+struct State {
+    waker: AtomicWaker,
+    handle: AtomicPtr<csdk::SPI_HandleTypeDef>,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            handle: AtomicPtr::new(core::ptr::null_mut()),
+        }
+    }
+}
+
+const SPI_COUNT: usize = 2;
+static STATES: [State; SPI_COUNT] = [State::new(), State::new()];
+
+unsafe fn on_irq(index: usize) {
+    let state = &STATES[index];
+    let handle = state.handle.load(Ordering::Relaxed);
+    if !handle.is_null() {
+        csdk::HAL_SPI_IRQHandler(handle);
+    }
+    state.waker.wake();
+}
+
+#[cfg(feature = "peri-spi1")]
+#[interrupt]
+unsafe fn SPI1() {
+    on_irq(0);
+}
+
+#[cfg(feature = "peri-spi2")]
+#[interrupt]
+unsafe fn SPI2() {
+    on_irq(1);
+}
+
+bitflags! {
+    pub struct SpiErrorFlags: u32 {
+        const MODE_FAULT = csdk::HAL_SPI_ERROR_MODF;
+        const OVERRUN = csdk::HAL_SPI_ERROR_OVR;
+        const FRAME_FORMAT = csdk::HAL_SPI_ERROR_FRE;
+        #[cfg(feature = "peri-dma")]
+        const DMA = csdk::HAL_SPI_ERROR_DMA;
+        const FLAG = csdk::HAL_SPI_ERROR_FLAG;
+    }
+}
+
+impl SpiErrorFlags {
+    /// The most specific [`embedded_hal_1::spi::ErrorKind`] for a set of flags.
+    pub fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        use embedded_hal_1::spi::ErrorKind;
+
+        if self.contains(Self::OVERRUN) {
+            ErrorKind::Overrun
+        } else if self.contains(Self::MODE_FAULT) {
+            ErrorKind::ModeFault
+        } else if self.contains(Self::FRAME_FORMAT) {
+            ErrorKind::FrameFormat
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// Order of the bits on the wire.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+/// SPI config
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// Highest SCK frequency in Hz, the prescaler is picked from the peripheral clock
+    /// so the actual frequency is at most this.
+    pub frequency: u32,
+    /// Clock polarity and phase.
+    pub mode: embedded_hal_1::spi::Mode,
+    pub bit_order: BitOrder,
+    pub timeout: Timeout,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            frequency: 1_000_000,
+            mode: embedded_hal_1::spi::MODE_0,
+            bit_order: BitOrder::MsbFirst,
+            timeout: Timeout::new_mill(1000),
+        }
+    }
+}
+
+impl Config {
+    fn init(&self) -> csdk::SPI_InitTypeDef {
+        csdk::SPI_InitTypeDef {
+            Mode: csdk::SPI_MODE_MASTER,
+            Direction: csdk::SPI_DIRECTION_2LINES,
+            DataSize: csdk::SPI_DATASIZE_8BIT,
+            CLKPolarity: match self.mode.polarity {
+                Polarity::IdleLow => csdk::SPI_POLARITY_LOW,
+                Polarity::IdleHigh => csdk::SPI_POLARITY_HIGH,
+            },
+            CLKPhase: match self.mode.phase {
+                Phase::CaptureOnFirstTransition => csdk::SPI_PHASE_1EDGE,
+                Phase::CaptureOnSecondTransition => csdk::SPI_PHASE_2EDGE,
+            },
+            NSS: csdk::SPI_NSS_SOFT,
+            BaudRatePrescaler: prescaler(rcc::get_pclk_freq(), self.frequency),
+            FirstBit: match self.bit_order {
+                BitOrder::MsbFirst => csdk::SPI_FIRSTBIT_MSB,
+                BitOrder::LsbFirst => csdk::SPI_FIRSTBIT_LSB,
+            },
+            SlaveFastMode: csdk::SPI_SLAVE_FAST_MODE_DISABLE,
+        }
+    }
+}
+
+/// Smallest prescaler that keeps SCK at or below `frequency`.
+fn prescaler(pclk: u32, frequency: u32) -> u32 {
+    const PRESCALERS: [u32; 8] = [
+        csdk::SPI_BAUDRATEPRESCALER_2,
+        csdk::SPI_BAUDRATEPRESCALER_4,
+        csdk::SPI_BAUDRATEPRESCALER_8,
+        csdk::SPI_BAUDRATEPRESCALER_16,
+        csdk::SPI_BAUDRATEPRESCALER_32,
+        csdk::SPI_BAUDRATEPRESCALER_64,
+        csdk::SPI_BAUDRATEPRESCALER_128,
+        csdk::SPI_BAUDRATEPRESCALER_256,
+    ];
+    let mut div = 2;
+    for prescaler in PRESCALERS {
+        if pclk / div <= frequency {
+            return prescaler;
+        }
+        div *= 2;
+    }
+    csdk::SPI_BAUDRATEPRESCALER_256
+}
+
+trait SealedWord {}
+
+/// Size of a word on the wire.
+#[allow(private_bounds)]
+pub trait Word: SealedWord + Copy + Default + 'static {
+    const DATA_SIZE: u32;
+}
+
+impl SealedWord for u8 {}
+impl Word for u8 {
+    const DATA_SIZE: u32 = csdk::SPI_DATASIZE_8BIT;
+}
+
+impl SealedWord for u16 {}
+impl Word for u16 {
+    const DATA_SIZE: u32 = csdk::SPI_DATASIZE_16BIT;
+}
+
+/// SPI master.
+pub struct Spi<M: Mode> {
+    pub handle: csdk::SPI_HandleTypeDef,
+    timeout: Timeout,
+    _phantom: PhantomData<M>,
+}
+
+impl Spi<Blocking> {
+    /// Create a new blocking SPI master.
+    pub fn new_blocking_from_csdk(instance: *mut csdk::SPI_TypeDef, config: Config) -> Result<Self, Error<SpiErrorFlags>> {
+        Self::new_inner(instance, config)
+    }
+
+    pub fn new_blocking(instance_num: u8, config: Config) -> Result<Self, Error<SpiErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_inner(instance, config)
+    }
+}
+
+impl Spi<Async> {
+    /// Create a new SPI master whose transfers run on linked DMA channels, see
+    /// [`dma::DmaChannel::link`]. Needs a TX and an RX channel.
+    pub fn new_from_csdk(instance: *mut csdk::SPI_TypeDef, config: Config) -> Result<Self, Error<SpiErrorFlags>> {
+        let this = Self::new_inner(instance, config)?;
+        enable_irq(instance);
+        Ok(this)
+    }
+
+    pub fn new(instance_num: u8, config: Config) -> Result<Self, Error<SpiErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, config)
+    }
+}
+
+fn instance_from_num(instance_num: u8) -> Result<*mut csdk::SPI_TypeDef, Error<SpiErrorFlags>> {
+    match instance_num {
+        #[cfg(feature = "peri-spi1")]
+        1 => Ok(csdk::SPI1),
+        #[cfg(feature = "peri-spi2")]
+        2 => Ok(csdk::SPI2),
+        _ => Err(Error::UserInput(InputError::InvalidInstance)),
+    }
+}
+
+fn enable_irq(instance: *mut csdk::SPI_TypeDef) {
+    let irqn: i32 = match instance {
+        #[cfg(feature = "peri-spi1")]
+        csdk::SPI1 => csdk::IRQn_Type_SPI1_IRQn,
+        #[cfg(feature = "peri-spi2")]
+        csdk::SPI2 => csdk::IRQn_Type_SPI2_IRQn,
+        // the instance was checked by `enable_and_init`
+        _ => unreachable!(),
+    };
+    unsafe {
+        csdk::HAL_NVIC_SetPriority(irqn, 0, 0);
+        csdk::HAL_NVIC_EnableIRQ(irqn);
+    }
+}
+
+/// Enable the clock of the instance and reset it.
+fn enable_and_reset(instance: *mut csdk::SPI_TypeDef) -> Result<(), Error<SpiErrorFlags>> {
+    unsafe {
+        match instance {
+            #[cfg(feature = "peri-spi1")]
+            csdk::SPI1 => {
+                csdk::HAL_RCC_SPI1_CLK_ENABLE();
+                csdk::HAL_RCC_SPI1_FORCE_RESET();
+                csdk::HAL_RCC_SPI1_RELEASE_RESET();
+                Ok(())
+            },
+            #[cfg(feature = "peri-spi2")]
+            csdk::SPI2 => {
+                csdk::HAL_RCC_SPI2_CLK_ENABLE();
+                csdk::HAL_RCC_SPI2_FORCE_RESET();
+                csdk::HAL_RCC_SPI2_RELEASE_RESET();
+                Ok(())
+            },
+            _ => Err(Error::UserInput(InputError::InvalidInstance)),
+        }
+    }
+}
+
+fn state_index(instance: *mut csdk::SPI_TypeDef) -> usize {
+    match instance {
+        #[cfg(feature = "peri-spi2")]
+        csdk::SPI2 => 1,
+        _ => 0,
+    }
+}
+
+impl<M: Mode> Spi<M> {
+    fn new_inner(instance: *mut csdk::SPI_TypeDef, config: Config) -> Result<Self, Error<SpiErrorFlags>> {
+        let mut handle: csdk::SPI_HandleTypeDef = unsafe { core::mem::zeroed() };
+        handle.Instance = instance;
+        handle.Init = config.init();
+
+        let mut this = Self {
+            handle,
+            timeout: config.timeout,
+            _phantom: PhantomData,
+        };
+        this.enable_and_init()?;
+        Ok(this)
+    }
+
+    fn enable_and_init(&mut self) -> Result<(), Error<SpiErrorFlags>> {
+        enable_and_reset(self.handle.Instance)?;
+        unsafe {
+            check(csdk::HAL_SPI_Init(&mut self.handle), ||self.gerr())
+        }
+    }
+
+    fn gerr(&self) -> Error<SpiErrorFlags> {
+        Error::HalError(SpiErrorFlags::from_bits_truncate(self.handle.ErrorCode))
+    }
+
+    /// Apply a new config, e.g. to talk to another device on the bus.
+    pub fn set_config(&mut self, config: &Config) -> Result<(), Error<SpiErrorFlags>> {
+        self.timeout = config.timeout;
+        self.reinit(config.init())
+    }
+
+    fn reinit(&mut self, init: csdk::SPI_InitTypeDef) -> Result<(), Error<SpiErrorFlags>> {
+        self.handle.Init = init;
+        unsafe {
+            check(csdk::HAL_SPI_DeInit(&mut self.handle), ||self.gerr())?;
+            check(csdk::HAL_SPI_Init(&mut self.handle), ||self.gerr())
+        }
+    }
+
+    /// Switch the frame format to `W` if needed.
+    fn set_word_size<W: Word>(&mut self) -> Result<(), Error<SpiErrorFlags>> {
+        if self.handle.Init.DataSize == W::DATA_SIZE {
+            return Ok(());
+        }
+        let mut init = self.handle.Init;
+        init.DataSize = W::DATA_SIZE;
+        self.reinit(init)
+    }
+
+    pub fn blocking_write<W: Word>(&mut self, words: &[W]) -> Result<(), Error<SpiErrorFlags>> {
+        if words.is_empty() {
+            return Ok(());
+        }
+        self.set_word_size::<W>()?;
+        for chunk in words.chunks(u16::MAX as usize) {
+            let result = unsafe {
+                csdk::HAL_SPI_Transmit(
+                    &mut self.handle,
+                    chunk.as_ptr() as *mut u8,
+                    chunk.len() as u16,
+                    self.timeout.get_tick(),
+                )
+            };
+            check(result, ||self.gerr())?;
+        }
+        Ok(())
+    }
+
+    /// Read words, the bus is clocked with unspecified data going out.
+    pub fn blocking_read<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error<SpiErrorFlags>> {
+        if words.is_empty() {
+            return Ok(());
+        }
+        self.set_word_size::<W>()?;
+        for chunk in words.chunks_mut(u16::MAX as usize) {
+            let result = unsafe {
+                csdk::HAL_SPI_Receive(
+                    &mut self.handle,
+                    chunk.as_mut_ptr() as *mut u8,
+                    chunk.len() as u16,
+                    self.timeout.get_tick(),
+                )
+            };
+            check(result, ||self.gerr())?;
+        }
+        Ok(())
+    }
+
+    /// Write and read at the same time, see [`embedded_hal_1::spi::SpiBus::transfer`]
+    /// for buffers of different lengths.
+    pub fn blocking_transfer<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<(), Error<SpiErrorFlags>> {
+        let common = read.len().min(write.len());
+        let (read, read_rest) = read.split_at_mut(common);
+        let (write, write_rest) = write.split_at(common);
+        if common > 0 {
+            self.set_word_size::<W>()?;
+        }
+        let chunk_len = u16::MAX as usize;
+        for (read, write) in read.chunks_mut(chunk_len).zip(write.chunks(chunk_len)) {
+            let result = unsafe {
+                csdk::HAL_SPI_TransmitReceive(
+                    &mut self.handle,
+                    write.as_ptr() as *mut u8,
+                    read.as_mut_ptr() as *mut u8,
+                    read.len() as u16,
+                    self.timeout.get_tick(),
+                )
+            };
+            check(result, ||self.gerr())?;
+        }
+        self.blocking_write(write_rest)?;
+        self.blocking_read(read_rest)
+    }
+
+    /// Write `words` and replace them with the words read at the same time.
+    pub fn blocking_transfer_in_place<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error<SpiErrorFlags>> {
+        if words.is_empty() {
+            return Ok(());
+        }
+        self.set_word_size::<W>()?;
+        for chunk in words.chunks_mut(u16::MAX as usize) {
+            // each word goes out before the word received with it is stored
+            let ptr = chunk.as_mut_ptr() as *mut u8;
+            let result = unsafe {
+                csdk::HAL_SPI_TransmitReceive(
+                    &mut self.handle,
+                    ptr,
+                    ptr,
+                    chunk.len() as u16,
+                    self.timeout.get_tick(),
+                )
+            };
+            check(result, ||self.gerr())?;
+        }
+        Ok(())
+    }
+}
+
+impl embedded_hal_1::spi::Error for Error<SpiErrorFlags> {
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        match self {
+            Error::HalError(error_flags) => error_flags.kind(),
+            _ => embedded_hal_1::spi::ErrorKind::Other,
+        }
+    }
+}
+
+impl<M: Mode> embedded_hal_1::spi::ErrorType for Spi<M> {
+    type Error = Error<SpiErrorFlags>;
+}
+
+impl<M: Mode, W: Word> embedded_hal_1::spi::SpiBus<W> for Spi<M> {
+    fn read(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        self.blocking_read(words)
+    }
+
+    fn write(&mut self, words: &[W]) -> Result<(), Self::Error> {
+        self.blocking_write(words)
+    }
+
+    fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), Self::Error> {
+        self.blocking_transfer(read, write)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        self.blocking_transfer_in_place(words)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // the CSDK polling API returns once the bus is idle
+        Ok(())
+    }
+}
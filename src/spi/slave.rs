@@ -0,0 +1,168 @@
+//! SPI slave, framed by the NSS line of the host
+
+use super::*;
+use super::dma_transfer::{dma_error, dma_request};
+use crate::exti::ExtiInput;
+
+/// SPI slave config
+#[derive(Copy, Clone)]
+pub struct SlaveConfig {
+    /// Clock polarity and phase, as driven by the host.
+    pub mode: embedded_hal_1::spi::Mode,
+    pub bit_order: BitOrder,
+}
+
+impl Default for SlaveConfig {
+    fn default() -> Self {
+        Self {
+            mode: embedded_hal_1::spi::MODE_0,
+            bit_order: BitOrder::MsbFirst,
+        }
+    }
+}
+
+impl SlaveConfig {
+    fn init(&self) -> csdk::SPI_InitTypeDef {
+        let mut init = Config {
+            mode: self.mode,
+            bit_order: self.bit_order,
+            ..Default::default()
+        }.init();
+        init.Mode = csdk::SPI_MODE_SLAVE;
+        init.BaudRatePrescaler = csdk::SPI_BAUDRATEPRESCALER_2;
+        init
+    }
+}
+
+/// SPI slave with DMA transfers framed by NSS.
+///
+/// NSS is watched through EXTI on a GPIO input and the peripheral uses software NSS: while
+/// a transaction is armed the slave counts as selected, so it must be the only device on its
+/// clock and data lines.
+///
+/// Needs a TX and an RX DMA channel linked with [`dma::DmaChannel::link`], which maps them
+/// to the requests of the instance.
+pub struct SpiSlave {
+    pub handle: csdk::SPI_HandleTypeDef,
+    nss: ExtiInput,
+}
+
+impl SpiSlave {
+    pub fn new_from_csdk(instance: *mut csdk::SPI_TypeDef, nss: ExtiInput, config: SlaveConfig) -> Result<Self, Error<SpiErrorFlags>> {
+        let mut handle: csdk::SPI_HandleTypeDef = unsafe { core::mem::zeroed() };
+        handle.Instance = instance;
+        handle.Init = config.init();
+
+        enable_and_reset(instance)?;
+        unsafe {
+            check(csdk::HAL_SPI_Init(&mut handle), ||Error::HalError(SpiErrorFlags::from_bits_truncate(handle.ErrorCode)))?;
+        }
+        enable_irq(instance);
+        Ok(Self { handle, nss })
+    }
+
+    pub fn new(instance_num: u8, nss: ExtiInput, config: SlaveConfig) -> Result<Self, Error<SpiErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, nss, config)
+    }
+
+    fn gerr(&self) -> Error<SpiErrorFlags> {
+        Error::HalError(SpiErrorFlags::from_bits_truncate(self.handle.ErrorCode))
+    }
+
+    /// Stop the transfer and the peripheral, so the next transaction starts with empty buffers.
+    fn stop(handle: *mut csdk::SPI_HandleTypeDef) {
+        unsafe {
+            csdk::HAL_SPI_Abort(handle);
+            (*(*handle).Instance).CR1 &= !csdk::SPI_CR1_SPE;
+            STATES[state_index((*handle).Instance)].handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        }
+    }
+
+    /// Serve one frame of the host.
+    ///
+    /// Waits until NSS is high, arms `tx` to be sent and `rx` to be filled, then waits for
+    /// NSS to fall and rise again. Returns how many bytes the host clocked, at most the
+    /// transfer length below.
+    ///
+    /// If both buffers are given, only the length of the shorter one is transferred. If `tx` is
+    /// empty zeros are sent, if `rx` is empty the received bytes are dropped. At most
+    /// `u16::MAX` bytes are transferred per frame.
+    pub async fn transaction(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Error<SpiErrorFlags>> {
+        let mut dummy_tx = 0u8;
+        let mut dummy_rx = 0u8;
+        let (tx_ptr, tx_inc, rx_ptr, rx_inc, len) = match (tx.is_empty(), rx.is_empty()) {
+            (true, true) => return Ok(0),
+            (true, false) => (&mut dummy_tx as *mut u8, false, rx.as_mut_ptr(), true, rx.len()),
+            (false, true) => (tx.as_ptr() as *mut u8, true, &mut dummy_rx as *mut u8, false, tx.len()),
+            (false, false) => (tx.as_ptr() as *mut u8, true, rx.as_mut_ptr(), true, tx.len().min(rx.len())),
+        };
+        let len = len.min(u16::MAX as usize);
+        let hdmatx = self.handle.hdmatx;
+        let hdmarx = self.handle.hdmarx;
+        if hdmatx.is_null() || hdmarx.is_null() {
+            return Err(Error::UserInput(InputError::MissingDma));
+        }
+
+        // never arm in the middle of a frame
+        self.nss.wait_for_high().await;
+
+        let handle = &mut self.handle as *mut csdk::SPI_HandleTypeDef;
+        unsafe {
+            dma::set_buffer_mode(hdmatx, false, tx_inc).map_err(dma_error)?;
+            dma::set_buffer_mode(hdmarx, false, rx_inc).map_err(dma_error)?;
+            // the channels may have been linked before the driver was moved
+            (*hdmatx).Parent = handle as *mut core::ffi::c_void;
+            (*hdmarx).Parent = handle as *mut core::ffi::c_void;
+            dma::enable_irq(hdmatx);
+            dma::enable_irq(hdmarx);
+        }
+
+        STATES[state_index(self.handle.Instance)].handle.store(handle, Ordering::Relaxed);
+        if let Err(e) = check(
+            unsafe { csdk::HAL_SPI_TransmitReceive_DMA(handle, tx_ptr, rx_ptr, len as u16) },
+            || self.gerr(),
+        ) {
+            Self::stop(handle);
+            return Err(e);
+        }
+        let on_drop = OnDrop::new(|| Self::stop(handle));
+
+        self.nss.wait_for_low().await;
+        self.nss.wait_for_high().await;
+
+        // the RX channel only counts bytes that were completely clocked in
+        let remaining = unsafe { core::ptr::read_volatile(&(*(*hdmarx).Instance).CNDTR) } as usize;
+        let clocked = len - remaining.min(len);
+        on_drop.defuse();
+        let error_code = unsafe { core::ptr::read_volatile(&(*handle).ErrorCode) };
+        Self::stop(handle);
+
+        // the host clocking past the end of the buffers overruns the receiver, that is not an error
+        if error_code != csdk::HAL_SPI_ERROR_NONE && clocked < len {
+            return Err(Error::HalError(SpiErrorFlags::from_bits_truncate(error_code)));
+        }
+        Ok(clocked)
+    }
+}
+
+impl dma::HasDmaField for SpiSlave {
+    /// A memory-to-peripheral channel becomes the TX channel, anything else the RX channel.
+    ///
+    /// `Parent` is pointed at the handle again before each transaction.
+    fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel) {
+        let tx = dma_handle.handle.Init.Direction == csdk::DMA_MEMORY_TO_PERIPH;
+        dma_handle.set_request(dma_request(self.handle.Instance, tx));
+        if tx {
+            self.handle.hdmatx = &mut dma_handle.handle;
+        } else {
+            self.handle.hdmarx = &mut dma_handle.handle;
+        }
+    }
+
+    fn get_handle_ptr(&mut self) -> *mut core::ffi::c_void {
+        &mut self.handle
+            as *mut csdk::SPI_HandleTypeDef
+            as *mut core::ffi::c_void
+    }
+}
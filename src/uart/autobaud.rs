@@ -0,0 +1,167 @@
+//! Automatic baud rate detection
+
+use super::*;
+
+/// What the USART measures to find the baud rate of the remote.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AutoBaudMode {
+    /// Length of the start bit, the first character must start with a 1 bit.
+    StartBit,
+    /// Falling edge to falling edge, the first character must start with `10xx`.
+    FallingEdge,
+    /// A `0x7F` character.
+    Frame0x7F,
+    /// A `0x55` character.
+    Frame0x55,
+}
+
+impl AutoBaudMode {
+    fn bits(self) -> u32 {
+        match self {
+            Self::StartBit => csdk::UART_ADVFEATURE_AUTOBAUDRATE_ONSTARTBIT,
+            Self::FallingEdge => csdk::UART_ADVFEATURE_AUTOBAUDRATE_ONFALLINGEDGE,
+            Self::Frame0x7F => csdk::UART_ADVFEATURE_AUTOBAUDRATE_ON0X7FFRAME,
+            Self::Frame0x55 => csdk::UART_ADVFEATURE_AUTOBAUDRATE_ON0X55FRAME,
+        }
+    }
+}
+
+/// What [`poll_auto_baud`] saw.
+enum AutoBaud {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Arm auto baud rate detection, the next received character is measured.
+fn arm_auto_baud(handle: &mut csdk::UART_HandleTypeDef, mode: AutoBaudMode) {
+    let instance = handle.Instance;
+    unsafe {
+        (*instance).CR3 &= !(csdk::USART_CR3_ABREN | csdk::USART_CR3_ABRMODE);
+        (*instance).SR &= !(csdk::USART_SR_ABRF | csdk::USART_SR_ABRE);
+        (*instance).CR3 |= mode.bits() | csdk::USART_CR3_ABREN;
+    }
+}
+
+fn poll_auto_baud(handle: &csdk::UART_HandleTypeDef) -> AutoBaud {
+    let sr = unsafe { core::ptr::read_volatile(&(*handle.Instance).SR) };
+    if sr & csdk::USART_SR_ABRE != 0 {
+        AutoBaud::Failed
+    } else if sr & csdk::USART_SR_ABRF != 0 {
+        AutoBaud::Done
+    } else {
+        AutoBaud::Pending
+    }
+}
+
+/// Disarm detection and drop the measured character.
+///
+/// Returns the detected baud rate, computed from BRR and the peripheral clock.
+fn finish_auto_baud(handle: &mut csdk::UART_HandleTypeDef) -> u32 {
+    let instance = handle.Instance;
+    let brr = unsafe {
+        (*instance).CR3 &= !csdk::USART_CR3_ABREN;
+        let _ = core::ptr::read_volatile(&(*instance).DR);
+        core::ptr::read_volatile(&(*instance).BRR)
+    };
+    let pclk = rcc::get_pclk_freq();
+    let baud = if handle.Init.OverSampling == csdk::UART_OVERSAMPLING_8 {
+        // BRR[2:0] holds DIV_Fraction[3:1]
+        let div = (brr & 0xfff0) | ((brr & 0x7) << 1);
+        2 * pclk / div.max(1)
+    } else {
+        pclk / brr.max(1)
+    };
+    handle.Init.BaudRate = baud;
+    baud
+}
+
+fn disarm_auto_baud(handle: &mut csdk::UART_HandleTypeDef) {
+    unsafe {
+        (*handle.Instance).CR3 &= !csdk::USART_CR3_ABREN;
+    }
+}
+
+impl<M: Mode> UartRx<M> {
+    /// Measure the baud rate of the remote on the next received character and switch to it.
+    ///
+    /// Returns the detected baud rate.
+    pub fn blocking_detect_baud(&mut self, mode: AutoBaudMode, timeout: Timeout) -> Result<u32, Error<UartErrorFlags>> {
+        arm_auto_baud(&mut self.handle, mode);
+        let tickstart = unsafe { csdk::HAL_GetTick() };
+        loop {
+            match poll_auto_baud(&self.handle) {
+                AutoBaud::Done => return Ok(finish_auto_baud(&mut self.handle)),
+                AutoBaud::Failed => {
+                    disarm_auto_baud(&mut self.handle);
+                    return Err(Error::HalError(UartErrorFlags::AUTO_BAUD_ERROR));
+                }
+                AutoBaud::Pending => (),
+            }
+            if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > timeout.get_tick() {
+                disarm_auto_baud(&mut self.handle);
+                return Err(Error::Timeout);
+            }
+        }
+    }
+}
+
+impl UartRx<Async> {
+    /// Measure the baud rate of the remote on the next received character and switch to it.
+    ///
+    /// The USART has no interrupt for the end of detection, the flag is polled without
+    /// blocking the executor. Returns the detected baud rate.
+    pub async fn detect_baud(&mut self, mode: AutoBaudMode, timeout: Timeout) -> Result<u32, Error<UartErrorFlags>> {
+        arm_auto_baud(&mut self.handle, mode);
+        let handle = &mut self.handle as *mut csdk::UART_HandleTypeDef;
+        let on_drop = OnDrop::new(|| disarm_auto_baud(unsafe { &mut *handle }));
+
+        let tickstart = unsafe { csdk::HAL_GetTick() };
+        let result = loop {
+            match poll_auto_baud(unsafe { &*handle }) {
+                AutoBaud::Done => break Ok(()),
+                AutoBaud::Failed => break Err(Error::HalError(UartErrorFlags::AUTO_BAUD_ERROR)),
+                AutoBaud::Pending => (),
+            }
+            if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > timeout.get_tick() {
+                break Err(Error::Timeout);
+            }
+            #[cfg(feature = "time")]
+            embassy_time::Timer::after_micros(100).await;
+            #[cfg(not(feature = "time"))]
+            embassy_futures::yield_now().await;
+        };
+
+        on_drop.defuse();
+        match result {
+            Ok(()) => Ok(finish_auto_baud(&mut self.handle)),
+            Err(e) => {
+                disarm_auto_baud(&mut self.handle);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<M: Mode> Uart<M> {
+    /// Measure the baud rate of the remote on the next received character and switch to it.
+    ///
+    /// Returns the detected baud rate.
+    pub fn blocking_detect_baud(&mut self, mode: AutoBaudMode, timeout: Timeout) -> Result<u32, Error<UartErrorFlags>> {
+        let baud = self.rx.blocking_detect_baud(mode, timeout)?;
+        self.tx.handle.Init.BaudRate = baud;
+        Ok(baud)
+    }
+}
+
+impl Uart<Async> {
+    /// Measure the baud rate of the remote on the next received character and switch to it.
+    ///
+    /// Returns the detected baud rate.
+    pub async fn detect_baud(&mut self, mode: AutoBaudMode, timeout: Timeout) -> Result<u32, Error<UartErrorFlags>> {
+        let baud = self.rx.detect_baud(mode, timeout).await?;
+        self.tx.handle.Init.BaudRate = baud;
+        Ok(baud)
+    }
+}
@@ -0,0 +1,448 @@
+//! Interrupt-driven UART with software FIFOs
+
+use core::sync::atomic::AtomicUsize;
+
+use super::*;
+
+/// Single-producer single-consumer byte queue over a user buffer.
+///
+/// `start` and `end` run over `0..2 * len`, so a full queue can be told apart from an empty one.
+struct RingBuffer {
+    buf: AtomicPtr<u8>,
+    len: AtomicUsize,
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+impl RingBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: AtomicPtr::new(core::ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    fn init(&self, buf: &mut [u8]) {
+        self.start.store(0, Ordering::Relaxed);
+        self.end.store(0, Ordering::Relaxed);
+        self.len.store(buf.len(), Ordering::Relaxed);
+        self.buf.store(buf.as_mut_ptr(), Ordering::Release);
+    }
+
+    fn deinit(&self) {
+        self.buf.store(core::ptr::null_mut(), Ordering::Release);
+        self.len.store(0, Ordering::Relaxed);
+    }
+
+    fn is_init(&self) -> bool {
+        !self.buf.load(Ordering::Acquire).is_null()
+    }
+
+    fn wrap(&self, n: usize) -> usize {
+        let len2 = 2 * self.len.load(Ordering::Relaxed);
+        if n >= len2 { n - len2 } else { n }
+    }
+
+    fn count(&self) -> usize {
+        let start = self.start.load(Ordering::Acquire);
+        let end = self.end.load(Ordering::Acquire);
+        if end >= start {
+            end - start
+        } else {
+            end + 2 * self.len.load(Ordering::Relaxed) - start
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.count() == self.len.load(Ordering::Relaxed)
+    }
+
+    /// The longest contiguous run of queued bytes.
+    fn readable(&self) -> (*mut u8, usize) {
+        let len = self.len.load(Ordering::Relaxed);
+        let offset = self.start.load(Ordering::Acquire) % len;
+        let buf = self.buf.load(Ordering::Relaxed);
+        (unsafe { buf.add(offset) }, self.count().min(len - offset))
+    }
+
+    /// Consumer side, drop `n` queued bytes.
+    fn pop(&self, n: usize) {
+        let start = self.start.load(Ordering::Relaxed);
+        self.start.store(self.wrap(start + n), Ordering::Release);
+    }
+
+    /// The longest contiguous run of free space.
+    fn writable(&self) -> (*mut u8, usize) {
+        let len = self.len.load(Ordering::Relaxed);
+        let offset = self.end.load(Ordering::Acquire) % len;
+        let buf = self.buf.load(Ordering::Relaxed);
+        (unsafe { buf.add(offset) }, (len - self.count()).min(len - offset))
+    }
+
+    /// Producer side, queue `n` bytes written into [`writable`](Self::writable).
+    fn push(&self, n: usize) {
+        let end = self.end.load(Ordering::Relaxed);
+        self.end.store(self.wrap(end + n), Ordering::Release);
+    }
+
+    fn pop_byte(&self) -> Option<u8> {
+        let (ptr, n) = self.readable();
+        if n == 0 {
+            return None;
+        }
+        let byte = unsafe { core::ptr::read_volatile(ptr) };
+        self.pop(1);
+        Some(byte)
+    }
+
+    fn push_byte(&self, byte: u8) -> bool {
+        let (ptr, n) = self.writable();
+        if n == 0 {
+            return false;
+        }
+        unsafe { core::ptr::write_volatile(ptr, byte) };
+        self.push(1);
+        true
+    }
+}
+
+struct Buffers {
+    rx: RingBuffer,
+    tx: RingBuffer,
+}
+
+impl Buffers {
+    const fn new() -> Self {
+        Self { rx: RingBuffer::new(), tx: RingBuffer::new() }
+    }
+}
+
+static BUFFERS: [Buffers; UART_COUNT] = [Buffers::new(), Buffers::new()];
+
+/// Move bytes between the USART and the FIFOs of a [`BufferedUart`], if there is one.
+pub(super) unsafe fn on_irq(index: usize) {
+    let buffers = &BUFFERS[index];
+    if !buffers.rx.is_init() {
+        return;
+    }
+    let state = &STATES[index];
+    let instance = instance_from_index(index);
+    let sr = core::ptr::read_volatile(&(*instance).SR);
+
+    let mut errors = UartErrorFlags::from_sr(sr);
+    if sr & csdk::USART_SR_RXNE != 0 || !errors.is_empty() {
+        // also clears the error flags
+        let byte = core::ptr::read_volatile(&(*instance).DR) as u8;
+        if sr & csdk::USART_SR_RXNE != 0 && !buffers.rx.push_byte(byte) {
+            errors |= UartErrorFlags::OVERRUN_ERROR;
+        }
+        if !errors.is_empty() {
+            state.add_errors(errors.bits());
+        }
+        state.rx_waker.wake();
+    }
+
+    let cr1 = (*instance).CR1;
+    if cr1 & csdk::USART_CR1_TXEIE != 0 && sr & csdk::USART_SR_TXE != 0 {
+        match buffers.tx.pop_byte() {
+            Some(byte) => core::ptr::write_volatile(&mut (*instance).DR, byte as u32),
+            None => {
+                // wait for the last byte to leave the shift register
+                (*instance).CR1 = (cr1 & !csdk::USART_CR1_TXEIE) | csdk::USART_CR1_TCIE;
+            }
+        }
+        state.tx_waker.wake();
+    } else if cr1 & csdk::USART_CR1_TCIE != 0 && sr & csdk::USART_SR_TC != 0 {
+        (*instance).CR1 = cr1 & !csdk::USART_CR1_TCIE;
+        state.tx_waker.wake();
+    }
+}
+
+/// UART that moves bytes between user buffers and the USART in its interrupt.
+///
+/// Reception runs all the time, a read only waits if nothing has arrived yet.
+/// Parity, noise, framing and overrun errors are reported by the next read,
+/// the received stream goes on after that.
+///
+/// Only full-duplex wiring without a driver-enable pin is supported, see
+/// [`BufferedUart::new_from_csdk`].
+pub struct BufferedUart<'d> {
+    instance: *mut csdk::USART_TypeDef,
+    _phantom: PhantomData<&'d mut [u8]>,
+}
+
+impl<'d> BufferedUart<'d> {
+    /// Both buffers must not be empty, otherwise `InputError::InvalidBufferLength` is returned.
+    ///
+    /// The interrupt never turns the line around, so `Duplex::Half` and `Config::de` are
+    /// rejected with `InputError::UnsupportedConfig`.
+    pub fn new_from_csdk(
+        instance: *mut csdk::USART_TypeDef,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, Error<UartErrorFlags>> {
+        if tx_buffer.is_empty() || rx_buffer.is_empty() {
+            return Err(Error::UserInput(InputError::InvalidBufferLength));
+        }
+        if config.duplex != Duplex::Full || config.de.is_some() {
+            return Err(Error::UserInput(InputError::UnsupportedConfig));
+        }
+        init_handle(instance, &config)?;
+
+        let index = state_index(instance);
+        let buffers = &BUFFERS[index];
+        buffers.tx.init(tx_buffer);
+        buffers.rx.init(rx_buffer);
+        STATES[index].errors.store(0, Ordering::Relaxed);
+
+        unsafe {
+            (*instance).CR1 |= csdk::USART_CR1_RXNEIE | csdk::USART_CR1_PEIE;
+            (*instance).CR3 |= csdk::USART_CR3_EIE;
+        }
+        enable_irq(instance);
+
+        Ok(Self { instance, _phantom: PhantomData })
+    }
+
+    pub fn new(
+        instance_num: u8,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, tx_buffer, rx_buffer, config)
+    }
+
+    fn state(&self) -> &'static State {
+        &STATES[state_index(self.instance)]
+    }
+
+    fn buffers(&self) -> &'static Buffers {
+        &BUFFERS[state_index(self.instance)]
+    }
+
+    fn take_errors(&self) -> Result<(), Error<UartErrorFlags>> {
+        let errors = UartErrorFlags::from_bits_truncate(self.state().take_errors());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::HalError(errors))
+        }
+    }
+
+    /// Copy queued bytes into `buf`, `None` if nothing is queued.
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Error<UartErrorFlags>> {
+        self.take_errors()?;
+        let rx = &self.buffers().rx;
+        let (ptr, n) = rx.readable();
+        if n == 0 {
+            return Ok(None);
+        }
+        let n = n.min(buf.len());
+        unsafe { core::ptr::copy_nonoverlapping(ptr, buf.as_mut_ptr(), n) };
+        rx.pop(n);
+        Ok(Some(n))
+    }
+
+    /// Queue as much of `buf` as fits, `None` if the queue is full.
+    fn try_write(&mut self, buf: &[u8]) -> Option<usize> {
+        let tx = &self.buffers().tx;
+        let (ptr, n) = tx.writable();
+        if n == 0 {
+            return None;
+        }
+        let n = n.min(buf.len());
+        unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), ptr, n) };
+        tx.push(n);
+        let instance = self.instance;
+        critical_section::with(|_| unsafe {
+            (*instance).CR1 |= csdk::USART_CR1_TXEIE;
+        });
+        Some(n)
+    }
+
+    fn is_flushed(&self) -> bool {
+        self.buffers().tx.is_empty()
+            && unsafe { core::ptr::read_volatile(&(*self.instance).SR) } & csdk::USART_SR_TC != 0
+    }
+
+    fn readable(&mut self) -> Result<&[u8], Error<UartErrorFlags>> {
+        self.take_errors()?;
+        let (ptr, n) = self.buffers().rx.readable();
+        Ok(unsafe { core::slice::from_raw_parts(ptr, n) })
+    }
+
+    /// Read the bytes received so far, waiting for at least one.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<UartErrorFlags>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        poll_fn(|cx| {
+            self.state().rx_waker.register(cx.waker());
+            match self.try_read(buf) {
+                Ok(None) => Poll::Pending,
+                Ok(Some(n)) => Poll::Ready(Ok(n)),
+                Err(e) => Poll::Ready(Err(e)),
+            }
+        }).await
+    }
+
+    /// Queue bytes of `buf` for transmission, waiting for space if the queue is full.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error<UartErrorFlags>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        poll_fn(|cx| {
+            self.state().tx_waker.register(cx.waker());
+            match self.try_write(buf) {
+                None => Poll::Pending,
+                Some(n) => Poll::Ready(Ok(n)),
+            }
+        }).await
+    }
+
+    /// Wait until every queued byte has been sent.
+    pub async fn flush(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        poll_fn(|cx| {
+            self.state().tx_waker.register(cx.waker());
+            if self.is_flushed() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        }).await
+    }
+
+    pub fn blocking_read(&mut self, buf: &mut [u8]) -> Result<usize, Error<UartErrorFlags>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(n) = self.try_read(buf)? {
+                return Ok(n);
+            }
+        }
+    }
+
+    pub fn blocking_write(&mut self, buf: &[u8]) -> Result<usize, Error<UartErrorFlags>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(n) = self.try_write(buf) {
+                return Ok(n);
+            }
+        }
+    }
+
+    pub fn blocking_flush(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        while !self.is_flushed() {}
+        Ok(())
+    }
+}
+
+impl Drop for BufferedUart<'_> {
+    fn drop(&mut self) {
+        let instance = self.instance;
+        critical_section::with(|_| unsafe {
+            (*instance).CR1 &= !(csdk::USART_CR1_RXNEIE
+                | csdk::USART_CR1_PEIE
+                | csdk::USART_CR1_TXEIE
+                | csdk::USART_CR1_TCIE);
+            (*instance).CR3 &= !csdk::USART_CR3_EIE;
+        });
+        let buffers = self.buffers();
+        buffers.rx.deinit();
+        buffers.tx.deinit();
+    }
+}
+
+impl embedded_io::ErrorType for BufferedUart<'_> {
+    type Error = Error<UartErrorFlags>;
+}
+
+impl embedded_io::Read for BufferedUart<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.blocking_read(buf)
+    }
+}
+
+impl embedded_io::BufRead for BufferedUart<'_> {
+    fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        while self.buffers().rx.is_empty() {
+            self.take_errors()?;
+        }
+        self.readable()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buffers().rx.pop(amt);
+    }
+}
+
+impl embedded_io::ReadReady for BufferedUart<'_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.buffers().rx.is_empty())
+    }
+}
+
+impl embedded_io::Write for BufferedUart<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.blocking_write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.blocking_flush()
+    }
+}
+
+impl embedded_io::WriteReady for BufferedUart<'_> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.buffers().tx.is_full())
+    }
+}
+
+impl embedded_io_async::Read for BufferedUart<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        BufferedUart::read(self, buf).await
+    }
+}
+
+impl embedded_io_async::BufRead for BufferedUart<'_> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        poll_fn(|cx| {
+            self.state().rx_waker.register(cx.waker());
+            if let Err(e) = self.take_errors() {
+                return Poll::Ready(Err(e));
+            }
+            if self.buffers().rx.is_empty() {
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(()))
+            }
+        }).await?;
+        self.readable()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buffers().rx.pop(amt);
+    }
+}
+
+impl embedded_io_async::Write for BufferedUart<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        BufferedUart::write(self, buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        BufferedUart::flush(self).await
+    }
+}
@@ -0,0 +1,248 @@
+//! UART transfers through linked DMA channels
+
+use super::*;
+
+fn dma_error(error: Error<UartErrorFlags>) -> Error<UartErrorFlags> {
+    match error {
+        // the CSDK only fails to start a DMA transfer with an empty error code
+        Error::HalError(flags) if flags.is_empty() => Error::HalError(UartErrorFlags::DMA_ERROR),
+        e => e,
+    }
+}
+
+/// Check the length against the 16-bit DMA count, set the channel up for a byte buffer and
+/// point it at `handle`.
+fn prepare_dma(
+    handle: &mut csdk::UART_HandleTypeDef,
+    hdma: *mut csdk::DMA_HandleTypeDef,
+    len: usize,
+) -> Result<(), Error<UartErrorFlags>> {
+    if len > u16::MAX as usize {
+        return Err(Error::UserInput(InputError::InvalidBufferLength));
+    }
+    if hdma.is_null() {
+        return Err(Error::UserInput(InputError::MissingDma));
+    }
+    unsafe {
+        dma::set_buffer_mode(hdma, false, true).map_err(|_| Error::HalError(UartErrorFlags::DMA_ERROR))?;
+        (*hdma).Parent = handle as *mut csdk::UART_HandleTypeDef as *mut core::ffi::c_void;
+        dma::enable_irq(hdma);
+    }
+    // the end of a transmission is signalled by the USART interrupt
+    enable_irq(handle.Instance);
+    Ok(())
+}
+
+/// Register `handle` with the interrupt handler, start a DMA transfer and spin until
+/// the transmitter (`is_tx`) or receiver is ready again.
+fn blocking_run_dma<F>(
+    handle: &mut csdk::UART_HandleTypeDef,
+    is_tx: bool,
+    timeout_tick: u32,
+    start: F,
+) -> Result<(), Error<UartErrorFlags>>
+where
+    F: FnOnce(*mut csdk::UART_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+{
+    let state = &STATES[state_index(handle.Instance)];
+    let registered = if is_tx { &state.tx_handle } else { &state.rx_handle };
+    let handle = handle as *mut csdk::UART_HandleTypeDef;
+    let gerr = || unsafe {
+        Error::HalError(UartErrorFlags::from_bits_truncate(core::ptr::read_volatile(&(*handle).ErrorCode)))
+    };
+    registered.store(handle, Ordering::Relaxed);
+
+    let result = (|| unsafe {
+        check(critical_section::with(|_| start(handle)), gerr)?;
+        let tickstart = csdk::HAL_GetTick();
+        loop {
+            let ready = if is_tx {
+                core::ptr::read_volatile(&(*handle).gState)
+            } else {
+                core::ptr::read_volatile(&(*handle).RxState)
+            } == csdk::HAL_UART_StateTypeDef_HAL_UART_STATE_READY;
+            if ready {
+                break;
+            }
+            if csdk::HAL_GetTick().wrapping_sub(tickstart) > timeout_tick {
+                abort(handle, is_tx);
+                return Err(Error::Timeout);
+            }
+        }
+        if core::ptr::read_volatile(&(*handle).ErrorCode) != csdk::HAL_UART_ERROR_NONE {
+            return Err(gerr());
+        }
+        Ok(())
+    })();
+
+    registered.store(core::ptr::null_mut(), Ordering::Relaxed);
+    result.map_err(dma_error)
+}
+
+impl<M: Mode> UartTx<M> {
+    /// Write `buffer` through the linked TX DMA channel.
+    ///
+    /// At most `u16::MAX` bytes, longer buffers are an `InputError::InvalidBufferLength`.
+    pub fn blocking_write_dma(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let hdma = self.handle.hdmatx;
+        prepare_dma(&mut self.handle, hdma, buffer.len())?;
+        self.begin_transmit();
+        let result = blocking_run_dma(&mut self.handle, true, self.timeout.get_tick(), |handle| unsafe {
+            csdk::HAL_UART_Transmit_DMA(handle, buffer.as_ptr() as *mut u8, buffer.len() as u16)
+        });
+        self.end_transmit();
+        self.recover_on_error(result)
+    }
+}
+
+impl UartTx<Async> {
+    /// Write `buffer` through the linked TX DMA channel.
+    ///
+    /// At most `u16::MAX` bytes, longer buffers are an `InputError::InvalidBufferLength`.
+    ///
+    /// Returns once the last byte has left the shift register.
+    pub async fn write_dma(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let hdma = self.handle.hdmatx;
+        prepare_dma(&mut self.handle, hdma, buffer.len())?;
+        self.begin_transmit();
+        self.release_de_on_tc();
+        let de_guard = self.de_guard();
+        let result = run_it(&mut self.handle, true, |handle| unsafe {
+            csdk::HAL_UART_Transmit_DMA(handle, buffer.as_ptr() as *mut u8, buffer.len() as u16)
+        }).await.map_err(dma_error);
+        de_guard.defuse();
+        self.end_transmit();
+        self.recover_on_error(result)
+    }
+}
+
+impl<M: Mode> UartRx<M> {
+    /// Fill `buffer` through the linked RX DMA channel.
+    ///
+    /// At most `u16::MAX` bytes, longer buffers are an `InputError::InvalidBufferLength`.
+    pub fn blocking_read_dma(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let hdma = self.handle.hdmarx;
+        prepare_dma(&mut self.handle, hdma, buffer.len())?;
+        self.begin_receive();
+        let result = blocking_run_dma(&mut self.handle, false, self.timeout.get_tick(), |handle| unsafe {
+            csdk::HAL_UART_Receive_DMA(handle, buffer.as_mut_ptr(), buffer.len() as u16)
+        });
+        self.recover_on_error(result)
+    }
+}
+
+impl UartRx<Async> {
+    /// Fill `buffer` through the linked RX DMA channel.
+    ///
+    /// At most `u16::MAX` bytes, longer buffers are an `InputError::InvalidBufferLength`.
+    pub async fn read_dma(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let hdma = self.handle.hdmarx;
+        prepare_dma(&mut self.handle, hdma, buffer.len())?;
+        self.begin_receive();
+        let result = run_it(&mut self.handle, false, |handle| unsafe {
+            csdk::HAL_UART_Receive_DMA(handle, buffer.as_mut_ptr(), buffer.len() as u16)
+        }).await.map_err(dma_error);
+        self.recover_on_error(result)
+    }
+}
+
+impl<M: Mode> Uart<M> {
+    /// Write `buffer` through the linked TX DMA channel.
+    pub fn blocking_write_dma(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.tx.blocking_write_dma(buffer)
+    }
+
+    /// Fill `buffer` through the linked RX DMA channel.
+    pub fn blocking_read_dma(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.rx.blocking_read_dma(buffer)
+    }
+}
+
+impl Uart<Async> {
+    /// Write `buffer` through the linked TX DMA channel.
+    pub async fn write_dma(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.tx.write_dma(buffer).await
+    }
+
+    /// Fill `buffer` through the linked RX DMA channel.
+    pub async fn read_dma(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.rx.read_dma(buffer).await
+    }
+}
+
+// Receptions end in the DMA interrupt, wake from the CSDK callbacks.
+
+#[no_mangle]
+unsafe extern "C" fn HAL_UART_RxCpltCallback(huart: *mut csdk::UART_HandleTypeDef) {
+    STATES[state_index((*huart).Instance)].rx_waker.wake();
+}
+
+#[no_mangle]
+unsafe extern "C" fn HAL_UART_TxCpltCallback(huart: *mut csdk::UART_HandleTypeDef) {
+    STATES[state_index((*huart).Instance)].tx_waker.wake();
+}
+
+/// The DMA request of the transmitter or the receiver of `instance`.
+pub(super) fn dma_request(instance: *mut csdk::USART_TypeDef, tx: bool) -> dma::DmaRequest {
+    match (state_index(instance), tx) {
+        (0, true) => dma::DmaRequest::Usart1Tx,
+        (0, false) => dma::DmaRequest::Usart1Rx,
+        (_, true) => dma::DmaRequest::Usart2Tx,
+        (_, false) => dma::DmaRequest::Usart2Rx,
+    }
+}
+
+impl<M: Mode> dma::HasDmaField for UartTx<M> {
+    fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel) {
+        dma_handle.set_request(dma_request(self.handle.Instance, true));
+        self.handle.hdmatx = &mut dma_handle.handle;
+    }
+
+    fn get_handle_ptr(&mut self) -> *mut core::ffi::c_void {
+        &mut self.handle
+            as *mut csdk::UART_HandleTypeDef
+            as *mut core::ffi::c_void
+    }
+}
+
+impl<M: Mode> dma::HasDmaField for UartRx<M> {
+    fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel) {
+        dma_handle.set_request(dma_request(self.handle.Instance, false));
+        self.handle.hdmarx = &mut dma_handle.handle;
+    }
+
+    fn get_handle_ptr(&mut self) -> *mut core::ffi::c_void {
+        &mut self.handle
+            as *mut csdk::UART_HandleTypeDef
+            as *mut core::ffi::c_void
+    }
+}
+
+impl<M: Mode> dma::HasDmaField for Uart<M> {
+    /// A memory-to-peripheral channel goes to the transmitter, anything else to the receiver.
+    ///
+    /// `Parent` is pointed at the right half again before each transfer.
+    fn set_dma_field(&mut self, dma_handle: &mut dma::DmaChannel) {
+        if dma_handle.handle.Init.Direction == csdk::DMA_MEMORY_TO_PERIPH {
+            dma::HasDmaField::set_dma_field(&mut self.tx, dma_handle);
+        } else {
+            dma::HasDmaField::set_dma_field(&mut self.rx, dma_handle);
+        }
+    }
+
+    fn get_handle_ptr(&mut self) -> *mut core::ffi::c_void {
+        dma::HasDmaField::get_handle_ptr(&mut self.tx)
+    }
+}
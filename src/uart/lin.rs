@@ -0,0 +1,249 @@
+//! Local Interconnect Network (LIN)
+//!
+//! Break generation and detection on the USART, plus frame helpers for masters and slaves.
+//! A frame is a header (break, sync byte, protected identifier) from the master followed by
+//! a response (data, checksum) from the master or a slave.
+
+use super::*;
+
+mod frame;
+pub use frame::*;
+
+/// Length of a low level the receiver takes as a break, see [`Config::lin`].
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BreakDetectLength {
+    Bits10,
+    Bits11,
+}
+
+impl BreakDetectLength {
+    pub(super) fn bits(self) -> u32 {
+        match self {
+            Self::Bits10 => csdk::UART_LINBREAKDETECTLENGTH_10B,
+            Self::Bits11 => csdk::UART_LINBREAKDETECTLENGTH_11B,
+        }
+    }
+}
+
+fn check_header(header: [u8; 2]) -> Result<u8, Error<UartErrorFlags>> {
+    if header[0] != SYNC {
+        return Err(Error::HalError(UartErrorFlags::LIN_SYNC_ERROR));
+    }
+    id_from_pid(header[1]).ok_or(Error::HalError(UartErrorFlags::LIN_PARITY_ERROR))
+}
+
+fn check_response(model: Checksum, id: u8, data: &[u8], received: u8) -> Result<(), Error<UartErrorFlags>> {
+    if checksum(model, id, data) != received {
+        return Err(Error::HalError(UartErrorFlags::LIN_CHECKSUM_ERROR));
+    }
+    Ok(())
+}
+
+impl<M: Mode> UartTx<M> {
+    /// Send a break (13 low bits) with `HAL_LIN_SendBreak` and wait until it is out.
+    pub fn send_break(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        self.begin_transmit();
+        let result = unsafe {
+            check(critical_section::with(|_| csdk::HAL_LIN_SendBreak(&mut self.handle)), ||self.gerr())
+        }.and_then(|_| {
+            // SBK is cleared by hardware during the stop bit of the break
+            let instance = self.handle.Instance;
+            let tickstart = unsafe { csdk::HAL_GetTick() };
+            while unsafe { core::ptr::read_volatile(&(*instance).CR1) } & csdk::USART_CR1_SBK != 0 {
+                if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > self.timeout.get_tick() {
+                    return Err(Error::Timeout);
+                }
+            }
+            Ok(())
+        });
+        self.end_transmit();
+        self.recover_on_error(result)
+    }
+}
+
+impl<M: Mode> UartRx<M> {
+    /// Spin until a break is detected, needs [`Config::lin`].
+    pub fn blocking_wait_for_break(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        let instance = self.handle.Instance;
+        let tickstart = unsafe { csdk::HAL_GetTick() };
+        while unsafe { core::ptr::read_volatile(&(*instance).SR) } & csdk::USART_SR_LBD == 0 {
+            if unsafe { csdk::HAL_GetTick() }.wrapping_sub(tickstart) > self.timeout.get_tick() {
+                return Err(Error::Timeout);
+            }
+        }
+        unsafe {
+            (*instance).SR = !csdk::USART_SR_LBD;
+        }
+        // drop the break character and its framing error
+        self.clear_errors();
+        Ok(())
+    }
+}
+
+impl UartRx<Async> {
+    /// Wait for a break, needs [`Config::lin`].
+    pub async fn wait_for_break(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        let instance = self.handle.Instance;
+        let state = &STATES[state_index(instance)];
+        state.lin_break.store(false, Ordering::Relaxed);
+        critical_section::with(|_| unsafe {
+            (*instance).SR = !csdk::USART_SR_LBD;
+            (*instance).CR2 |= csdk::USART_CR2_LBDIE;
+        });
+        let _disable = OnDrop::new(|| critical_section::with(|_| unsafe {
+            (*instance).CR2 &= !csdk::USART_CR2_LBDIE;
+        }));
+
+        poll_fn(|cx| {
+            state.rx_waker.register(cx.waker());
+            if state.take_lin_break() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }).await;
+
+        // drop the break character and its framing error
+        self.clear_errors();
+        Ok(())
+    }
+}
+
+impl<M: Mode> Uart<M> {
+    /// Send a break, see [`UartTx::send_break`].
+    pub fn send_break(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        self.tx.send_break()
+    }
+
+    /// Spin until a break is detected, needs [`Config::lin`].
+    pub fn blocking_wait_for_break(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        self.rx.blocking_wait_for_break()
+    }
+}
+
+impl Uart<Async> {
+    /// Wait for a break, needs [`Config::lin`].
+    pub async fn wait_for_break(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        self.rx.wait_for_break().await
+    }
+}
+
+/// LIN master, sends the headers and publishes or reads the responses.
+///
+/// The receiver must not read back the master's own transmission: use a transceiver without
+/// echo, or `Duplex::Half` together with [`Config::lin`].
+pub struct LinMaster<'a, M: Mode> {
+    uart: &'a mut Uart<M>,
+    checksum: Checksum,
+}
+
+impl<'a, M: Mode> LinMaster<'a, M> {
+    pub fn new(uart: &'a mut Uart<M>, checksum: Checksum) -> Self {
+        Self { uart, checksum }
+    }
+
+    fn blocking_header(&mut self, id: u8) -> Result<(), Error<UartErrorFlags>> {
+        self.uart.send_break()?;
+        self.uart.blocking_write(&[SYNC, pid(id)])
+    }
+
+    /// Send the header of frame `id` followed by `data` and its checksum.
+    pub fn blocking_write_frame(&mut self, id: u8, data: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.blocking_header(id)?;
+        self.uart.blocking_write(data)?;
+        self.uart.blocking_write(&[checksum(self.checksum, id, data)])
+    }
+
+    /// Send the header of frame `id` and read the slave's response into `data`.
+    pub fn blocking_read_frame(&mut self, id: u8, data: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.blocking_header(id)?;
+        self.uart.blocking_read(data)?;
+        let mut received = [0u8];
+        self.uart.blocking_read(&mut received)?;
+        check_response(self.checksum, id, data, received[0])
+    }
+}
+
+impl LinMaster<'_, Async> {
+    async fn header(&mut self, id: u8) -> Result<(), Error<UartErrorFlags>> {
+        self.uart.send_break()?;
+        self.uart.write(&[SYNC, pid(id)]).await
+    }
+
+    /// Send the header of frame `id` followed by `data` and its checksum.
+    pub async fn write_frame(&mut self, id: u8, data: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.header(id).await?;
+        self.uart.write(data).await?;
+        self.uart.write(&[checksum(self.checksum, id, data)]).await
+    }
+
+    /// Send the header of frame `id` and read the slave's response into `data`.
+    pub async fn read_frame(&mut self, id: u8, data: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.header(id).await?;
+        self.uart.read(data).await?;
+        let mut received = [0u8];
+        self.uart.read(&mut received).await?;
+        check_response(self.checksum, id, data, received[0])
+    }
+}
+
+/// LIN slave, waits for headers and answers or reads the responses.
+///
+/// The USART must run in LIN mode, see [`Config::lin`].
+pub struct LinSlave<'a, M: Mode> {
+    uart: &'a mut Uart<M>,
+    checksum: Checksum,
+}
+
+impl<'a, M: Mode> LinSlave<'a, M> {
+    pub fn new(uart: &'a mut Uart<M>, checksum: Checksum) -> Self {
+        Self { uart, checksum }
+    }
+
+    /// Wait for a header, returns the frame id.
+    pub fn blocking_wait_header(&mut self) -> Result<u8, Error<UartErrorFlags>> {
+        self.uart.blocking_wait_for_break()?;
+        let mut header = [0u8; 2];
+        self.uart.blocking_read(&mut header)?;
+        check_header(header)
+    }
+
+    /// Answer the header of frame `id` with `data` and its checksum.
+    pub fn blocking_respond(&mut self, id: u8, data: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.uart.blocking_write(data)?;
+        self.uart.blocking_write(&[checksum(self.checksum, id, data)])
+    }
+
+    /// Read the response to frame `id` published by the master or another slave.
+    pub fn blocking_read_response(&mut self, id: u8, data: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.uart.blocking_read(data)?;
+        let mut received = [0u8];
+        self.uart.blocking_read(&mut received)?;
+        check_response(self.checksum, id, data, received[0])
+    }
+}
+
+impl LinSlave<'_, Async> {
+    /// Wait for a header, returns the frame id.
+    pub async fn wait_header(&mut self) -> Result<u8, Error<UartErrorFlags>> {
+        self.uart.wait_for_break().await?;
+        let mut header = [0u8; 2];
+        self.uart.read(&mut header).await?;
+        check_header(header)
+    }
+
+    /// Answer the header of frame `id` with `data` and its checksum.
+    pub async fn respond(&mut self, id: u8, data: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.uart.write(data).await?;
+        self.uart.write(&[checksum(self.checksum, id, data)]).await
+    }
+
+    /// Read the response to frame `id` published by the master or another slave.
+    pub async fn read_response(&mut self, id: u8, data: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.uart.read(data).await?;
+        let mut received = [0u8];
+        self.uart.read(&mut received).await?;
+        check_response(self.checksum, id, data, received[0])
+    }
+}
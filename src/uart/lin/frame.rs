@@ -0,0 +1,97 @@
+//! LIN frame arithmetic: protected identifiers and checksums
+//!
+//! Free of dependencies, so the tests run on the host:
+//! `rustc --edition 2021 --test src/uart/lin/frame.rs -o target/lin-frame && target/lin-frame`
+
+/// Sync byte following the break of every header.
+pub const SYNC: u8 = 0x55;
+
+/// Checksum model of a frame.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Checksum {
+    /// LIN 1.x, over the data only.
+    Classic,
+    /// LIN 2.x, over the protected identifier and the data.
+    /// Diagnostic frames (`0x3C`, `0x3D`) still use the classic checksum.
+    Enhanced,
+}
+
+/// Protected identifier of the 6-bit frame `id`, with the parity bits P0 (bit 6) and P1 (bit 7).
+pub const fn pid(id: u8) -> u8 {
+    let id = id & 0x3f;
+    let p0 = (id ^ (id >> 1) ^ (id >> 2) ^ (id >> 4)) & 1;
+    let p1 = !((id >> 1) ^ (id >> 3) ^ (id >> 4) ^ (id >> 5)) & 1;
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Frame id of a protected identifier, `None` if the parity bits do not match.
+pub const fn id_from_pid(protected_id: u8) -> Option<u8> {
+    let id = protected_id & 0x3f;
+    if pid(id) == protected_id {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Checksum of the frame `id` carrying `data`.
+pub fn checksum(model: Checksum, id: u8, data: &[u8]) -> u8 {
+    let enhanced = model == Checksum::Enhanced && (id & 0x3f) < 0x3c;
+    let mut sum: u16 = if enhanced { pid(id) as u16 } else { 0 };
+    for &byte in data {
+        // sum with carry wrap-around
+        sum += byte as u16;
+        if sum > 0xff {
+            sum -= 0xff;
+        }
+    }
+    !(sum as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_parity() {
+        assert_eq!(pid(0x00), 0x80);
+        assert_eq!(pid(0x01), 0xc1);
+        assert_eq!(pid(0x10), 0x50);
+        assert_eq!(pid(0x3c), 0x3c);
+        assert_eq!(pid(0x3d), 0x7d);
+        assert_eq!(pid(0x3f), 0xbf);
+        // only the low 6 bits are the id
+        assert_eq!(pid(0xc1), 0xc1);
+    }
+
+    #[test]
+    fn id_from_pid_checks_parity() {
+        for id in 0..0x40 {
+            assert_eq!(id_from_pid(pid(id)), Some(id));
+        }
+        assert_eq!(id_from_pid(0x00), None);
+        assert_eq!(id_from_pid(0x3d), None);
+    }
+
+    #[test]
+    fn classic_checksum() {
+        assert_eq!(checksum(Checksum::Classic, 0x0a, &[0x55, 0x93, 0xe5]), 0x31);
+        assert_eq!(checksum(Checksum::Classic, 0x0a, &[]), 0xff);
+    }
+
+    #[test]
+    fn enhanced_checksum() {
+        // PID 0xca
+        assert_eq!(checksum(Checksum::Enhanced, 0x0a, &[0x55, 0x93, 0xe5]), 0x66);
+        // diagnostic frames stay classic
+        assert_eq!(checksum(Checksum::Enhanced, 0x3c, &[0x55, 0x93, 0xe5]), 0x31);
+        assert_eq!(checksum(Checksum::Enhanced, 0x3d, &[0x55, 0x93, 0xe5]), 0x31);
+    }
+
+    #[test]
+    fn checksum_carry_wraps() {
+        assert_eq!(checksum(Checksum::Classic, 0, &[0xff, 0x01]), 0xfe);
+        assert_eq!(checksum(Checksum::Classic, 0, &[0xff; 8]), 0x00);
+    }
+}
@@ -0,0 +1,63 @@
+//! defmt global logger over a USART
+//!
+//! For boards without a debug probe: the encoded frames are written to a USART in blocking
+//! mode and can be decoded on the host with `defmt-print` from a USB-serial adapter.
+//! Do not link another global logger (like `defmt-rtt`) in the same binary.
+
+use core::sync::atomic::AtomicBool;
+
+use super::*;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static mut CS_RESTORE: critical_section::RestoreState = critical_section::RestoreState::invalid();
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+static mut UART: Option<UartTx<Blocking>> = None;
+
+/// Send the defmt log frames through `uart` from now on.
+///
+/// Frames logged before are dropped.
+pub fn init_logger(uart: UartTx<Blocking>) {
+    critical_section::with(|_| unsafe {
+        *core::ptr::addr_of_mut!(UART) = Some(uart);
+    });
+}
+
+fn write(bytes: &[u8]) {
+    // only called between `acquire` and `release`, inside the critical section
+    if let Some(uart) = unsafe { &mut *core::ptr::addr_of_mut!(UART) } {
+        // there is nowhere to report a failed log write
+        let _ = uart.blocking_write(bytes);
+    }
+}
+
+#[defmt::global_logger]
+struct UartLogger;
+
+unsafe impl defmt::Logger for UartLogger {
+    fn acquire() {
+        let restore = unsafe { critical_section::acquire() };
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly")
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+        unsafe {
+            CS_RESTORE = restore;
+            (*core::ptr::addr_of_mut!(ENCODER)).start_frame(write);
+        }
+    }
+
+    unsafe fn flush() {
+        // every byte is out once `blocking_write` returns
+    }
+
+    unsafe fn release() {
+        (*core::ptr::addr_of_mut!(ENCODER)).end_frame(write);
+        TAKEN.store(false, Ordering::Relaxed);
+        let restore = CS_RESTORE;
+        critical_section::release(restore);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*core::ptr::addr_of_mut!(ENCODER)).write(bytes, write);
+    }
+}
@@ -0,0 +1,1159 @@
+//! Universal Asynchronous Receiver Transmitter (UART)
+
+
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+use core::task::Poll;
+
+use embedded_hal as embedded_hal_1;
+use embassy_sync::waitqueue::AtomicWaker;
+
+#[cfg(feature = "time")]
+use embassy_time::Duration;
+use defmt::bitflags;
+
+use csdk_hal::check;
+use crate::*;
+use crate::csdk::interrupts::interrupt;
+use crate::mode::{Async, Blocking, Mode};
+
+mod autobaud;
+pub use autobaud::*;
+mod buffered;
+pub use buffered::*;
+pub mod lin;
+#[cfg(feature = "peri-dma")]
+mod dma_transfer;
+#[cfg(feature = "peri-dma")]
+mod ringbuffered;
+#[cfg(feature = "peri-dma")]
+pub use ringbuffered::*;
+#[cfg(feature = "defmt-uart")]
+mod logger;
+#[cfg(feature = "defmt-uart")]
+pub use logger::*;
+
+/// Per-instance interrupt state.
+///
+/// The handles point at the `UART_HandleTypeDef` of a [`UartTx`] or [`UartRx`] only while an
+/// IT transfer is running, so the interrupt handler never touches a handle that may have been moved.
+///
+/// Both halves change the shared CR1 through their own handle, they only do so inside a
+/// critical section or from the interrupt handler.
+struct State {
+    tx_waker: AtomicWaker,
+    rx_waker: AtomicWaker,
+    tx_handle: AtomicPtr<csdk::UART_HandleTypeDef>,
+    rx_handle: AtomicPtr<csdk::UART_HandleTypeDef>,
+    /// Errors reported by the CSDK that did not end the transfer (parity, noise, framing).
+    errors: AtomicU32,
+    /// Times a circular reception wrapped around, see [`RingBufferedUartRx`].
+    laps: AtomicU32,
+    /// Set by the interrupt handler on a LIN break, see [`UartRx::wait_for_break`].
+    lin_break: AtomicBool,
+    /// Set by the interrupt handler on an idle line, see [`UartRx::read_until_idle`].
+    idle: AtomicBool,
+    /// Driver-enable pin the interrupt handler drops on transmission complete, set while the
+    /// last part of an async transmission runs, see [`Config::de`].
+    de_port: AtomicPtr<csdk::GPIO_TypeDef>,
+    de_pin: AtomicU32,
+    /// Microseconds between transmission complete and dropping the driver-enable pin.
+    de_deassert_us: AtomicU32,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            tx_waker: AtomicWaker::new(),
+            rx_waker: AtomicWaker::new(),
+            tx_handle: AtomicPtr::new(core::ptr::null_mut()),
+            rx_handle: AtomicPtr::new(core::ptr::null_mut()),
+            errors: AtomicU32::new(0),
+            laps: AtomicU32::new(0),
+            lin_break: AtomicBool::new(false),
+            idle: AtomicBool::new(false),
+            de_port: AtomicPtr::new(core::ptr::null_mut()),
+            de_pin: AtomicU32::new(0),
+            de_deassert_us: AtomicU32::new(0),
+        }
+    }
+
+    // thumbv6m has no atomic read-modify-write, these run in a critical section.
+
+    fn add_errors(&self, bits: u32) {
+        critical_section::with(|_| {
+            self.errors.store(self.errors.load(Ordering::Relaxed) | bits, Ordering::Relaxed);
+        });
+    }
+
+    fn take_errors(&self) -> u32 {
+        critical_section::with(|_| {
+            let bits = self.errors.load(Ordering::Relaxed);
+            self.errors.store(0, Ordering::Relaxed);
+            bits
+        })
+    }
+
+    fn add_lap(&self) {
+        critical_section::with(|_| {
+            self.laps.store(self.laps.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+        });
+    }
+
+    fn take_lin_break(&self) -> bool {
+        critical_section::with(|_| {
+            let set = self.lin_break.load(Ordering::Relaxed);
+            self.lin_break.store(false, Ordering::Relaxed);
+            set
+        })
+    }
+
+    /// Take the driver-enable pin back from the interrupt handler, true if the handler
+    /// has dropped it already.
+    fn disarm_de(&self) -> bool {
+        critical_section::with(|_| {
+            let released = self.de_port.load(Ordering::Relaxed).is_null();
+            self.de_port.store(core::ptr::null_mut(), Ordering::Relaxed);
+            released
+        })
+    }
+
+    fn take_idle(&self) -> bool {
+        critical_section::with(|_| {
+            let set = self.idle.load(Ordering::Relaxed);
+            self.idle.store(false, Ordering::Relaxed);
+            set
+        })
+    }
+}
+
+const UART_COUNT: usize = 2;
+static STATES: [State; UART_COUNT] = [State::new(), State::new()];
+
+unsafe fn on_irq(index: usize) {
+    let state = &STATES[index];
+    let instance = instance_from_index(index);
+    // IDLE is only enabled by circular receptions and `read_until_idle`. A pending byte is
+    // served first, its DR read clears IDLE too.
+    let idle = (*instance).CR1 & csdk::USART_CR1_IDLEIE != 0 && (*instance).SR & csdk::USART_SR_IDLE != 0;
+    if idle {
+        state.idle.store(true, Ordering::Relaxed);
+    }
+    // LBDIE is only enabled by `wait_for_break`, LBD is cleared by writing 0
+    if (*instance).CR2 & csdk::USART_CR2_LBDIE != 0 && (*instance).SR & csdk::USART_SR_LBD != 0 {
+        (*instance).SR = !csdk::USART_SR_LBD;
+        (*instance).CR2 &= !csdk::USART_CR2_LBDIE;
+        state.lin_break.store(true, Ordering::Relaxed);
+    }
+    buffered::on_irq(index);
+    // `HAL_UART_IRQHandler` serves whatever event it finds first and ends a transmission on
+    // TC without looking at the state of the handle, so it only gets the receiver's events.
+    let rx_handle = state.rx_handle.load(Ordering::Relaxed);
+    if !rx_handle.is_null() && rx_pending(instance) {
+        csdk::HAL_UART_IRQHandler(rx_handle);
+    }
+    if idle {
+        // clear IDLE by reading SR then DR
+        let _ = core::ptr::read_volatile(&(*instance).SR);
+        let _ = core::ptr::read_volatile(&(*instance).DR);
+    }
+    let tx_handle = state.tx_handle.load(Ordering::Relaxed);
+    if !tx_handle.is_null() {
+        on_tx_irq(state, instance, tx_handle);
+    }
+    state.tx_waker.wake();
+    state.rx_waker.wake();
+}
+
+/// Whether `HAL_UART_IRQHandler` would take its reception or error path.
+unsafe fn rx_pending(instance: *mut csdk::USART_TypeDef) -> bool {
+    let sr = core::ptr::read_volatile(&(*instance).SR);
+    let cr1 = core::ptr::read_volatile(&(*instance).CR1);
+    let cr3 = core::ptr::read_volatile(&(*instance).CR3);
+    let errors = sr & (csdk::USART_SR_PE | csdk::USART_SR_FE | csdk::USART_SR_NE | csdk::USART_SR_ORE);
+    (sr & csdk::USART_SR_RXNE != 0 && cr1 & csdk::USART_CR1_RXNEIE != 0)
+        || (errors != 0
+            && (cr3 & csdk::USART_CR3_EIE != 0
+                || cr1 & (csdk::USART_CR1_RXNEIE | csdk::USART_CR1_PEIE) != 0))
+}
+
+/// Serve an IT or DMA transmission of `handle`, like the CSDK's `UART_Transmit_IT` and
+/// `UART_EndTransmit_IT`.
+unsafe fn on_tx_irq(state: &State, instance: *mut csdk::USART_TypeDef, handle: *mut csdk::UART_HandleTypeDef) {
+    let sr = core::ptr::read_volatile(&(*instance).SR);
+    let cr1 = core::ptr::read_volatile(&(*instance).CR1);
+    if cr1 & csdk::USART_CR1_TXEIE != 0 && sr & csdk::USART_SR_TXE != 0 {
+        if (*handle).gState != csdk::HAL_UART_StateTypeDef_HAL_UART_STATE_BUSY_TX {
+            return;
+        }
+        let ptr = (*handle).pTxBuffPtr;
+        if (*handle).Init.WordLength == csdk::UART_WORDLENGTH_9B && (*handle).Init.Parity == csdk::UART_PARITY_NONE {
+            let word = core::ptr::read_unaligned(ptr as *const u16) as u32 & 0x1ff;
+            core::ptr::write_volatile(&mut (*instance).DR, word);
+            (*handle).pTxBuffPtr = ptr.add(2);
+        } else {
+            core::ptr::write_volatile(&mut (*instance).DR, *ptr as u32);
+            (*handle).pTxBuffPtr = ptr.add(1);
+        }
+        (*handle).TxXferCount -= 1;
+        if (*handle).TxXferCount == 0 {
+            // wait for the last byte to leave the shift register
+            (*instance).CR1 = (cr1 & !csdk::USART_CR1_TXEIE) | csdk::USART_CR1_TCIE;
+        }
+    } else if cr1 & csdk::USART_CR1_TCIE != 0 && sr & csdk::USART_SR_TC != 0 {
+        (*instance).CR1 = cr1 & !csdk::USART_CR1_TCIE;
+        (*handle).gState = csdk::HAL_UART_StateTypeDef_HAL_UART_STATE_READY;
+        // release the line right away, whatever the latency of the task
+        let de_port = state.de_port.load(Ordering::Relaxed);
+        if !de_port.is_null() {
+            csdk_hal::delay_us(state.de_deassert_us.load(Ordering::Relaxed));
+            csdk::HAL_GPIO_WritePin(de_port, state.de_pin.load(Ordering::Relaxed) as u16, csdk::GPIO_PinState_GPIO_PIN_RESET);
+            state.de_port.store(core::ptr::null_mut(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Overrides the weak CSDK callback.
+///
+/// In IT mode parity, noise and framing errors do not stop the reception and the CSDK clears
+/// `ErrorCode` right after this callback, keep them for the reader.
+#[no_mangle]
+unsafe extern "C" fn HAL_UART_ErrorCallback(huart: *mut csdk::UART_HandleTypeDef) {
+    let state = &STATES[state_index((*huart).Instance)];
+    state.add_errors((*huart).ErrorCode);
+    state.tx_waker.wake();
+    state.rx_waker.wake();
+}
+
+#[interrupt]
+unsafe fn USART1() {
+    on_irq(0);
+}
+
+#[interrupt]
+unsafe fn USART2() {
+    on_irq(1);
+}
+
+fn instance_from_num(instance_num: u8) -> Result<*mut csdk::USART_TypeDef, Error<UartErrorFlags>> {
+    match instance_num {
+        // #[cfg(feature = "peri-usart1")]
+        1 => Ok(csdk::USART1),
+        // #[cfg(feature = "peri-usart2")]
+        2 => Ok(csdk::USART2),
+        // TODO
+        _ => Err(Error::UserInput(InputError::InvalidInstance)),
+    }
+}
+
+fn enable_irq(instance: *mut csdk::USART_TypeDef) {
+    let irqn: i32 = match instance {
+        csdk::USART1 => csdk::IRQn_Type_USART1_IRQn,
+        csdk::USART2 => csdk::IRQn_Type_USART2_IRQn,
+        // the instance was checked by `enable_and_init`
+        _ => unreachable!(),
+    };
+    unsafe {
+        csdk::HAL_NVIC_SetPriority(irqn, 0, 0);
+        csdk::HAL_NVIC_EnableIRQ(irqn);
+    }
+}
+
+fn instance_from_index(index: usize) -> *mut csdk::USART_TypeDef {
+    match index {
+        1 => csdk::USART2,
+        _ => csdk::USART1,
+    }
+}
+
+fn state_index(instance: *mut csdk::USART_TypeDef) -> usize {
+    match instance {
+        csdk::USART2 => 1,
+        _ => 0,
+    }
+}
+
+/// How the USART is wired.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Duplex {
+    /// Separate TX and RX lines.
+    Full,
+    /// Single wire on the TX pin (`HAL_HalfDuplex_Init`), the driver switches between
+    /// transmitter and receiver.
+    Half,
+}
+
+pub struct Config {
+    pub init: csdk::UART_InitTypeDef,
+    pub advanced_init: csdk::UART_AdvFeatureInitTypeDef,
+    timeout: Timeout,
+    /// Clear the USART error flags and the CSDK error state after a failed transfer,
+    /// so the next transfer starts clean. The error is still returned.
+    pub auto_recover: bool,
+    pub duplex: Duplex,
+    /// Driver-enable pin of an RS-485 transceiver, switched to output by the driver.
+    /// Raised before transmitting and dropped after transmission complete, by the interrupt
+    /// handler for interrupt-driven and DMA transmissions.
+    pub de: Option<gpio::AnyPin>,
+    /// Bit times between raising `de` and the first start bit.
+    pub de_assert_bits: u32,
+    /// Bit times between transmission complete and dropping `de`.
+    pub de_deassert_bits: u32,
+    /// Run the USART in LIN mode (`HAL_LIN_Init`), needed to detect breaks.
+    ///
+    /// LIN mode needs the single-wire mode off, so `duplex` does not select the wiring here:
+    /// with `Duplex::Half` the USART stays on both lines and the driver only switches the
+    /// receiver off while transmitting, so a transceiver's echo is not read back.
+    pub lin: Option<lin::BreakDetectLength>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            init: csdk::UART_InitTypeDef {
+                BaudRate: 115200,
+                WordLength: csdk::UART_WORDLENGTH_8B,
+                StopBits: csdk::UART_STOPBITS_1,
+                Parity: csdk::UART_PARITY_NONE,
+                HwFlowCtl: csdk::UART_HWCONTROL_NONE,
+                OverSampling: csdk::UART_OVERSAMPLING_16,
+                Mode: csdk::UART_MODE_TX_RX,
+            },
+            advanced_init: csdk::UART_AdvFeatureInitTypeDef {
+                AdvFeatureInit: csdk::UART_ADVFEATURE_NO_INIT,
+                AutoBaudRateEnable: csdk::UART_ADVFEATURE_AUTOBAUDRATE_DISABLE,
+                AutoBaudRateMode: 0,
+            },
+            timeout:Timeout::new_mill(2000),
+            auto_recover: false,
+            duplex: Duplex::Full,
+            de: None,
+            de_assert_bits: 0,
+            de_deassert_bits: 0,
+            lin: None,
+        }
+    }
+}
+
+/// Serial error
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum SerialError {
+    /// Framing error
+    Framing,
+    /// Noise error
+    Noise,
+    /// RX buffer overrun
+    Overrun,
+    /// Parity check error
+    Parity,
+    /// Buffer too large for DMA
+    BufferTooLong,
+    /// DMA transfer error
+    Dma,
+}
+
+
+bitflags! {
+    pub struct UartErrorFlags: u32 {
+        const PARITY_ERROR = csdk::HAL_UART_ERROR_PE;
+        const NOISE_ERROR = csdk::HAL_UART_ERROR_NE;
+        const FRAME_ERROR = csdk::HAL_UART_ERROR_FE;
+        const OVERRUN_ERROR = csdk::HAL_UART_ERROR_ORE;
+        #[cfg(feature = "peri-dma")]
+        const DMA_ERROR = csdk::HAL_UART_ERROR_DMA;
+        /// Not a CSDK error code, auto baud rate detection failed.
+        const AUTO_BAUD_ERROR = 1 << 16;
+        /// Not a CSDK error code, a LIN header did not start with the sync byte.
+        const LIN_SYNC_ERROR = 1 << 17;
+        /// Not a CSDK error code, a LIN protected identifier failed its parity check.
+        const LIN_PARITY_ERROR = 1 << 18;
+        /// Not a CSDK error code, a LIN frame failed its checksum.
+        const LIN_CHECKSUM_ERROR = 1 << 19;
+        //#[cfg(feature = "register-callbacks")]
+        //const INVALID_CALLBACK = HAL_UART_ERROR_INVALID_CALLBACK;
+    }
+}
+
+impl SerialError {
+    /// Decode a CSDK `ErrorCode`.
+    ///
+    /// If several errors are flagged, the one that lost the most data wins:
+    /// overrun, framing, noise, parity, then DMA.
+    pub fn from_error_code(error_code: u32) -> Option<Self> {
+        let flags = UartErrorFlags::from_bits_truncate(error_code);
+        if flags.contains(UartErrorFlags::OVERRUN_ERROR) {
+            Some(Self::Overrun)
+        } else if flags.contains(UartErrorFlags::FRAME_ERROR) {
+            Some(Self::Framing)
+        } else if flags.contains(UartErrorFlags::NOISE_ERROR) {
+            Some(Self::Noise)
+        } else if flags.contains(UartErrorFlags::PARITY_ERROR) {
+            Some(Self::Parity)
+        } else if error_code & csdk::HAL_UART_ERROR_DMA != 0 {
+            Some(Self::Dma)
+        } else {
+            None
+        }
+    }
+
+    pub fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Framing | Self::Noise | Self::Parity => embedded_io::ErrorKind::InvalidData,
+            Self::BufferTooLong => embedded_io::ErrorKind::InvalidInput,
+            Self::Overrun | Self::Dma => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl UartErrorFlags {
+    /// The most severe error in the flags, see [`SerialError::from_error_code`].
+    pub fn serial_error(&self) -> Option<SerialError> {
+        SerialError::from_error_code(self.bits())
+    }
+
+    /// Decode the error bits of the status register.
+    fn from_sr(sr: u32) -> Self {
+        let mut flags = Self::empty();
+        if sr & csdk::USART_SR_PE != 0 {
+            flags |= Self::PARITY_ERROR;
+        }
+        if sr & csdk::USART_SR_NE != 0 {
+            flags |= Self::NOISE_ERROR;
+        }
+        if sr & csdk::USART_SR_FE != 0 {
+            flags |= Self::FRAME_ERROR;
+        }
+        if sr & csdk::USART_SR_ORE != 0 {
+            flags |= Self::OVERRUN_ERROR;
+        }
+        flags
+    }
+}
+
+
+/// Bidirectional UART driver.
+///
+/// Use [`split`](Self::split) to drive transmitter and receiver from different tasks.
+pub struct Uart<M: Mode> {
+    tx: UartTx<M>,
+    rx: UartRx<M>,
+}
+
+/// Transmitting half of a UART.
+pub struct UartTx<M: Mode> {
+    pub handle: csdk::UART_HandleTypeDef,
+    timeout: Timeout,
+    auto_recover: bool,
+    half_duplex: bool,
+    de: Option<DriverEnable>,
+    _phantom: PhantomData<M>,
+}
+
+/// Receiving half of a UART.
+pub struct UartRx<M: Mode> {
+    pub handle: csdk::UART_HandleTypeDef,
+    timeout: Timeout,
+    auto_recover: bool,
+    half_duplex: bool,
+    _phantom: PhantomData<M>,
+}
+
+/// Driver-enable line of an RS-485 transceiver.
+struct DriverEnable {
+    pin: gpio::AnyPin,
+    assert_bits: u32,
+    deassert_bits: u32,
+    /// Handed to the interrupt handler for the current transmission, see
+    /// [`UartTx::release_de_on_tc`].
+    on_tc: bool,
+}
+
+impl DriverEnable {
+    fn take(config: &mut Config) -> Option<Self> {
+        let mut pin = config.de.take()?;
+        pin.set_as_output(gpio::Speed::VeryHigh);
+        pin.set_low();
+        Some(Self {
+            pin,
+            assert_bits: config.de_assert_bits,
+            deassert_bits: config.de_deassert_bits,
+            on_tc: false,
+        })
+    }
+}
+
+/// Microseconds of `bits` bit times at the configured baud rate.
+fn bits_to_us(handle: &csdk::UART_HandleTypeDef, bits: u32) -> u32 {
+    (bits as u64 * 1_000_000).div_ceil(handle.Init.BaudRate as u64) as u32
+}
+
+/// Busy-wait for `bits` bit times at the configured baud rate.
+fn delay_bits(handle: &csdk::UART_HandleTypeDef, bits: u32) {
+    if bits == 0 {
+        return;
+    }
+    csdk_hal::delay_us(bits_to_us(handle, bits));
+}
+
+impl Uart<Blocking> {
+    /// Create a new blocking UART driver.
+    pub fn new_blocking_from_csdk(instance: *mut csdk::USART_TypeDef, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        Self::new_inner(instance, config)
+    }
+
+    pub fn new_blocking(instance_num: u8, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_inner(instance, config)
+    }
+}
+
+impl Uart<Async> {
+    /// Create a new interrupt-driven UART driver.
+    pub fn new_from_csdk(instance: *mut csdk::USART_TypeDef, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let this = Self::new_inner(instance, config)?;
+        enable_irq(instance);
+        Ok(this)
+    }
+
+    pub fn new(instance_num: u8, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, config)
+    }
+
+    /// Write `buffer`, waiting on the USART interrupt instead of polling.
+    ///
+    /// Returns once the last byte has left the shift register.
+    pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.tx.write(buffer).await
+    }
+
+    /// Fill `buffer`, waiting on the USART interrupt instead of polling.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.rx.read(buffer).await
+    }
+
+    /// See [`UartRx::read_until_idle`].
+    pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error<UartErrorFlags>> {
+        self.rx.read_until_idle(buffer).await
+    }
+
+    /// Drop the transmitter and turn the receiver into a [`RingBufferedUartRx`].
+    #[cfg(feature = "peri-dma")]
+    pub fn into_ring_buffered<'d>(
+        self,
+        dma: &'d mut dma::DmaChannel,
+        buffer: &'d mut [u8],
+    ) -> Result<RingBufferedUartRx<'d>, Error<UartErrorFlags>> {
+        self.rx.into_ring_buffered(dma, buffer)
+    }
+}
+
+impl<M: Mode> Uart<M> {
+    fn new_inner(instance: *mut csdk::USART_TypeDef, mut config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let timeout = config.timeout;
+        let auto_recover = config.auto_recover;
+        let half_duplex = config.duplex == Duplex::Half;
+        let de = DriverEnable::take(&mut config);
+        let handle = init_handle(instance, &config)?;
+        Ok(Self {
+            tx: UartTx { handle, timeout, auto_recover, half_duplex, de, _phantom: PhantomData },
+            rx: UartRx { handle, timeout, auto_recover, half_duplex, _phantom: PhantomData },
+        })
+    }
+
+    /// Split into a transmitter and a receiver that can be used from different tasks.
+    pub fn split(self) -> (UartTx<M>, UartRx<M>) {
+        (self.tx, self.rx)
+    }
+
+    pub fn blocking_write(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.tx.blocking_write(buffer)
+    }
+
+    pub fn blocking_read(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.rx.blocking_read(buffer)
+    }
+
+    /// Clear the USART error flags and the CSDK error state of both halves.
+    pub fn clear_errors(&mut self) {
+        self.tx.clear_errors();
+        self.rx.clear_errors();
+    }
+}
+
+impl UartTx<Blocking> {
+    /// Create a new blocking transmit-only driver, the USART runs in `UART_MODE_TX`.
+    pub fn new_blocking_from_csdk(instance: *mut csdk::USART_TypeDef, mut config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        config.init.Mode = csdk::UART_MODE_TX;
+        Self::new_inner(instance, config)
+    }
+
+    pub fn new_blocking(instance_num: u8, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_blocking_from_csdk(instance, config)
+    }
+}
+
+impl UartTx<Async> {
+    /// Create a new interrupt-driven transmit-only driver, the USART runs in `UART_MODE_TX`.
+    pub fn new_from_csdk(instance: *mut csdk::USART_TypeDef, mut config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        config.init.Mode = csdk::UART_MODE_TX;
+        let this = Self::new_inner(instance, config)?;
+        enable_irq(instance);
+        Ok(this)
+    }
+
+    pub fn new(instance_num: u8, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, config)
+    }
+
+    /// Write `buffer`, waiting on the USART interrupt instead of polling.
+    ///
+    /// Returns once the last byte has left the shift register.
+    pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.begin_transmit();
+        let de_guard = self.de_guard();
+        let mut result = Ok(());
+        let mut chunks = buffer.chunks(u16::MAX as usize).peekable();
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                self.release_de_on_tc();
+            }
+            result = run_it(&mut self.handle, true, |handle| unsafe {
+                csdk::HAL_UART_Transmit_IT(handle, chunk.as_ptr() as *mut u8, chunk.len() as u16)
+            }).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        de_guard.defuse();
+        self.end_transmit();
+        self.recover_on_error(result)
+    }
+}
+
+impl<M: Mode> UartTx<M> {
+    fn new_inner(instance: *mut csdk::USART_TypeDef, mut config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let timeout = config.timeout;
+        let auto_recover = config.auto_recover;
+        let half_duplex = config.duplex == Duplex::Half;
+        let de = DriverEnable::take(&mut config);
+        let handle = init_handle(instance, &config)?;
+        Ok(Self { handle, timeout, auto_recover, half_duplex, de, _phantom: PhantomData })
+    }
+
+    /// Take the line: switch a half-duplex USART to its transmitter and raise the
+    /// driver-enable pin.
+    fn begin_transmit(&mut self) {
+        if self.half_duplex {
+            critical_section::with(|_| unsafe { csdk::HAL_HalfDuplex_EnableTransmitter(&mut self.handle); });
+        }
+        if let Some(de) = &mut self.de {
+            de.on_tc = false;
+            de.pin.set_high();
+            delay_bits(&self.handle, de.assert_bits);
+        }
+    }
+
+    /// Let the interrupt handler drop the driver-enable pin on transmission complete of the
+    /// next interrupt-driven or DMA transmission.
+    fn release_de_on_tc(&mut self) {
+        if let Some(de) = &mut self.de {
+            let state = &STATES[state_index(self.handle.Instance)];
+            state.de_pin.store(de.pin.pin as u32, Ordering::Relaxed);
+            state.de_deassert_us.store(bits_to_us(&self.handle, de.deassert_bits), Ordering::Relaxed);
+            state.de_port.store(de.pin.port, Ordering::Relaxed);
+            de.on_tc = true;
+        }
+    }
+
+    /// Release the line, called once transmission complete is set.
+    fn end_transmit(&mut self) {
+        if let Some(de) = &mut self.de {
+            let state = &STATES[state_index(self.handle.Instance)];
+            // the interrupt handler missed it if the transmission failed
+            if !(de.on_tc && state.disarm_de()) {
+                delay_bits(&self.handle, de.deassert_bits);
+                de.pin.set_low();
+            }
+            de.on_tc = false;
+        }
+        if self.half_duplex {
+            critical_section::with(|_| unsafe { csdk::HAL_HalfDuplex_EnableReceiver(&mut self.handle); });
+        }
+    }
+
+    /// Drops the driver-enable pin if a transfer future is dropped half way.
+    fn de_guard(&self) -> OnDrop<impl FnOnce()> {
+        let pin = self.de.as_ref().map(|de| de.pin.clone());
+        let state = &STATES[state_index(self.handle.Instance)];
+        OnDrop::new(move || {
+            if let Some(mut pin) = pin {
+                state.disarm_de();
+                pin.set_low();
+            }
+        })
+    }
+
+    fn gerr(&self) -> Error<UartErrorFlags> {
+        Error::HalError(UartErrorFlags::from_bits_truncate(self.handle.ErrorCode))
+    }
+
+    /// Reset the CSDK error state of the transmitter.
+    ///
+    /// The USART error flags belong to the receiver, see [`UartRx::clear_errors`].
+    pub fn clear_errors(&mut self) {
+        self.handle.ErrorCode = csdk::HAL_UART_ERROR_NONE;
+    }
+
+    fn recover_on_error<T, E>(&mut self, result: Result<T, E>) -> Result<T, E> {
+        if result.is_err() && self.auto_recover {
+            self.clear_errors();
+        }
+        result
+    }
+
+    pub fn blocking_write(&mut self, buffer: &[u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.begin_transmit();
+        let mut result = Ok(());
+        for chunk in buffer.chunks(u16::MAX as usize) {
+            result = unsafe {
+                check(csdk::HAL_UART_Transmit(&mut self.handle,
+                    chunk.as_ptr() as *mut u8,
+                    chunk.len() as u16,
+                    self.timeout.get_tick()), ||self.gerr())
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        self.end_transmit();
+        self.recover_on_error(result)
+    }
+}
+
+impl UartRx<Blocking> {
+    /// Create a new blocking receive-only driver, the USART runs in `UART_MODE_RX`.
+    pub fn new_blocking_from_csdk(instance: *mut csdk::USART_TypeDef, mut config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        config.init.Mode = csdk::UART_MODE_RX;
+        Self::new_inner(instance, config)
+    }
+
+    pub fn new_blocking(instance_num: u8, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_blocking_from_csdk(instance, config)
+    }
+}
+
+impl UartRx<Async> {
+    /// Create a new interrupt-driven receive-only driver, the USART runs in `UART_MODE_RX`.
+    pub fn new_from_csdk(instance: *mut csdk::USART_TypeDef, mut config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        config.init.Mode = csdk::UART_MODE_RX;
+        let this = Self::new_inner(instance, config)?;
+        enable_irq(instance);
+        Ok(this)
+    }
+
+    pub fn new(instance_num: u8, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let instance = instance_from_num(instance_num)?;
+        Self::new_from_csdk(instance, config)
+    }
+
+    /// Fill `buffer`, waiting on the USART interrupt instead of polling.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.begin_receive();
+        for chunk in buffer.chunks_mut(u16::MAX as usize) {
+            let result = run_it(&mut self.handle, false, |handle| unsafe {
+                csdk::HAL_UART_Receive_IT(handle, chunk.as_mut_ptr(), chunk.len() as u16)
+            }).await;
+            self.recover_on_error(result)?;
+        }
+        Ok(())
+    }
+
+    /// Read until `buffer` is full, or until the line goes idle after at least one byte,
+    /// and return the number of bytes read. At most `u16::MAX` bytes are read per call.
+    ///
+    /// If the returned future is dropped, the reception is aborted and the bytes read so far
+    /// are in `buffer`.
+    pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error<UartErrorFlags>> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        self.begin_receive();
+        let len = buffer.len().min(u16::MAX as usize);
+        let instance = self.handle.Instance;
+        let state = &STATES[state_index(instance)];
+        let handle = &mut self.handle as *mut csdk::UART_HandleTypeDef;
+        state.rx_handle.store(handle, Ordering::Relaxed);
+        state.errors.store(0, Ordering::Relaxed);
+
+        let status = critical_section::with(|_| unsafe {
+            // forget an idle line from before this reception, a byte still in DR stays
+            let sr = core::ptr::read_volatile(&(*instance).SR);
+            if sr & csdk::USART_SR_IDLE != 0 && sr & csdk::USART_SR_RXNE == 0 {
+                let _ = core::ptr::read_volatile(&(*instance).DR);
+            }
+            state.idle.store(false, Ordering::Relaxed);
+            let status = csdk::HAL_UART_Receive_IT(handle, buffer.as_mut_ptr(), len as u16);
+            if status == csdk::HAL_StatusTypeDef_HAL_OK {
+                (*instance).CR1 |= csdk::USART_CR1_IDLEIE;
+            }
+            status
+        });
+        if let Err(e) = check(status, || self.gerr()) {
+            state.rx_handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+            return self.recover_on_error(Err(e));
+        }
+
+        let on_drop = OnDrop::new(|| {
+            end_idle_reception(handle);
+            state.rx_handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+        });
+
+        poll_fn(|cx| {
+            state.rx_waker.register(cx.waker());
+            let (rx_state, remaining) = unsafe {
+                (core::ptr::read_volatile(&(*handle).RxState), core::ptr::read_volatile(&(*handle).RxXferCount))
+            };
+            // an idle line before the first byte does not end the read
+            if rx_state == csdk::HAL_UART_StateTypeDef_HAL_UART_STATE_READY
+                || (state.take_idle() && remaining as usize != len)
+            {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }).await;
+
+        on_drop.defuse();
+        let received = end_idle_reception(handle);
+        state.rx_handle.store(core::ptr::null_mut(), Ordering::Relaxed);
+
+        let error_code = self.handle.ErrorCode | state.take_errors();
+        if error_code != csdk::HAL_UART_ERROR_NONE {
+            return self.recover_on_error(Err(Error::HalError(UartErrorFlags::from_bits_truncate(error_code))));
+        }
+        Ok(received)
+    }
+}
+
+impl<M: Mode> UartRx<M> {
+    fn new_inner(instance: *mut csdk::USART_TypeDef, config: Config) -> Result<Self, Error<UartErrorFlags>> {
+        let timeout = config.timeout;
+        let auto_recover = config.auto_recover;
+        let half_duplex = config.duplex == Duplex::Half;
+        let handle = init_handle(instance, &config)?;
+        Ok(Self { handle, timeout, auto_recover, half_duplex, _phantom: PhantomData })
+    }
+
+    /// Switch a half-duplex USART to its receiver.
+    fn begin_receive(&mut self) {
+        if self.half_duplex {
+            critical_section::with(|_| unsafe { csdk::HAL_HalfDuplex_EnableReceiver(&mut self.handle); });
+        }
+    }
+
+    fn gerr(&self) -> Error<UartErrorFlags> {
+        Error::HalError(UartErrorFlags::from_bits_truncate(self.handle.ErrorCode))
+    }
+
+    /// Clear the USART error flags and the CSDK error state.
+    pub fn clear_errors(&mut self) {
+        clear_errors(&mut self.handle);
+    }
+
+    fn recover_on_error<T, E>(&mut self, result: Result<T, E>) -> Result<T, E> {
+        if result.is_err() && self.auto_recover {
+            self.clear_errors();
+        }
+        result
+    }
+
+    pub fn blocking_read(&mut self, buffer: &mut [u8]) -> Result<(), Error<UartErrorFlags>> {
+        self.begin_receive();
+        for chunk in buffer.chunks_mut(u16::MAX as usize) {
+            let result = unsafe {
+                check(csdk::HAL_UART_Receive(&mut self.handle,
+                    chunk.as_mut_ptr(),
+                    chunk.len() as u16,
+                    self.timeout.get_tick()), ||self.gerr())
+            };
+            self.recover_on_error(result)?;
+        }
+        Ok(())
+    }
+}
+
+/// Clear the error flags (by reading SR then DR), reset the CSDK error code and drop the
+/// errors kept for the reader.
+fn clear_errors(handle: &mut csdk::UART_HandleTypeDef) {
+    let instance = handle.Instance;
+    unsafe {
+        let sr = core::ptr::read_volatile(&(*instance).SR);
+        if !UartErrorFlags::from_sr(sr).is_empty() {
+            let _ = core::ptr::read_volatile(&(*instance).DR);
+        }
+    }
+    handle.ErrorCode = csdk::HAL_UART_ERROR_NONE;
+    STATES[state_index(instance)].errors.store(0, Ordering::Relaxed);
+}
+
+/// Enable the clock and initialize the USART, the returned handle is copied into each half.
+fn init_handle(instance: *mut csdk::USART_TypeDef, config: &Config) -> Result<csdk::UART_HandleTypeDef, Error<UartErrorFlags>> {
+    let mut handle = csdk::UART_HandleTypeDef {
+        Instance: instance,
+        Init: config.init,
+        AdvancedInit: config.advanced_init,
+        pTxBuffPtr: core::ptr::null_mut(),
+        TxXferSize: 0,
+        TxXferCount: 0,
+        pRxBuffPtr: core::ptr::null_mut(),
+        RxXferSize: 0,
+        RxXferCount: 0,
+        hdmatx: core::ptr::null_mut(),
+        hdmarx: core::ptr::null_mut(),
+        Lock: 0,
+        gState: 0,
+        RxState: 0,
+        ErrorCode: 0,
+    };
+    unsafe{
+        match instance {
+            csdk::USART1 => {
+                csdk::HAL_RCC_USART1_CLK_ENABLE();
+                Ok(())
+            },
+            csdk::USART2 => {
+                csdk::HAL_RCC_USART2_CLK_ENABLE();
+                Ok(())
+            },
+            _ => Err(Error::UserInput(InputError::InvalidInstance)),
+        }?;
+        let status = match (config.lin, config.duplex) {
+            // single-wire mode must stay off in LIN mode
+            (Some(length), _) => csdk::HAL_LIN_Init(&mut handle, length.bits()),
+            (None, Duplex::Full) => csdk::HAL_UART_Init(&mut handle),
+            (None, Duplex::Half) => csdk::HAL_HalfDuplex_Init(&mut handle),
+        };
+        check(status, ||Error::HalError(UartErrorFlags::from_bits_truncate(handle.ErrorCode)))?;
+    }
+    Ok(handle)
+}
+
+/// Stop a reception of [`UartRx::read_until_idle`] and return the bytes it received.
+fn end_idle_reception(handle: *mut csdk::UART_HandleTypeDef) -> usize {
+    critical_section::with(|_| unsafe {
+        let instance = (*handle).Instance;
+        (*instance).CR1 &= !csdk::USART_CR1_IDLEIE;
+        // aborting resets the count
+        let received = ((*handle).RxXferSize - (*handle).RxXferCount) as usize;
+        if (*handle).RxState != csdk::HAL_UART_StateTypeDef_HAL_UART_STATE_READY {
+            csdk::HAL_UART_AbortReceive(handle);
+        }
+        received
+    })
+}
+
+/// Abort the transmission (`is_tx`) or reception of `handle`.
+fn abort(handle: *mut csdk::UART_HandleTypeDef, is_tx: bool) {
+    critical_section::with(|_| unsafe {
+        if is_tx {
+            csdk::HAL_UART_AbortTransmit(handle);
+        } else {
+            csdk::HAL_UART_AbortReceive(handle);
+        }
+    });
+}
+
+/// Register `handle` with the interrupt handler, start an IT transfer and wait until
+/// the transmitter (`is_tx`) or receiver is ready again.
+///
+/// If the returned future is dropped before the transfer is done, the transfer is aborted.
+async fn run_it<F>(handle: &mut csdk::UART_HandleTypeDef, is_tx: bool, start: F) -> Result<(), Error<UartErrorFlags>>
+where
+    F: FnOnce(*mut csdk::UART_HandleTypeDef) -> csdk::HAL_StatusTypeDef,
+{
+    let state = &STATES[state_index(handle.Instance)];
+    let registered = if is_tx { &state.tx_handle } else { &state.rx_handle };
+    let handle = handle as *mut csdk::UART_HandleTypeDef;
+    registered.store(handle, Ordering::Relaxed);
+    if !is_tx {
+        state.errors.store(0, Ordering::Relaxed);
+    }
+
+    if let Err(e) = check(critical_section::with(|_| start(handle)), || unsafe {
+        Error::HalError(UartErrorFlags::from_bits_truncate((*handle).ErrorCode))
+    }) {
+        registered.store(core::ptr::null_mut(), Ordering::Relaxed);
+        return Err(e);
+    }
+
+    let on_drop = OnDrop::new(|| {
+        abort(handle, is_tx);
+        registered.store(core::ptr::null_mut(), Ordering::Relaxed);
+    });
+
+    poll_fn(|cx| {
+        let busy = unsafe {
+            if is_tx {
+                state.tx_waker.register(cx.waker());
+                core::ptr::read_volatile(&(*handle).gState)
+            } else {
+                state.rx_waker.register(cx.waker());
+                core::ptr::read_volatile(&(*handle).RxState)
+            }
+        } != csdk::HAL_UART_StateTypeDef_HAL_UART_STATE_READY;
+        if busy {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }).await;
+
+    on_drop.defuse();
+    registered.store(core::ptr::null_mut(), Ordering::Relaxed);
+
+    let errors = if is_tx {
+        0
+    } else {
+        state.take_errors()
+    };
+    let error_code = unsafe { core::ptr::read_volatile(&(*handle).ErrorCode) } | errors;
+    if error_code != csdk::HAL_UART_ERROR_NONE {
+        return Err(Error::HalError(UartErrorFlags::from_bits_truncate(error_code)));
+    }
+    Ok(())
+}
+
+
+impl embedded_io::Error for Error<UartErrorFlags> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::HalError(flags) => flags
+                .serial_error()
+                .map_or(embedded_io::ErrorKind::Other, |e| e.kind()),
+            Error::Timeout => embedded_io::ErrorKind::TimedOut,
+            Error::UserInput(_) => embedded_io::ErrorKind::InvalidInput,
+            Error::Busy => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_io::Error for Error<SerialError> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::HalError(e) => e.kind(),
+            Error::Timeout => embedded_io::ErrorKind::TimedOut,
+            Error::UserInput(_) => embedded_io::ErrorKind::InvalidInput,
+            Error::Busy => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl<M: Mode> embedded_io::ErrorType for Uart<M> {
+    type Error = Error<UartErrorFlags>;
+}
+
+impl<M: Mode> embedded_io::ErrorType for UartTx<M> {
+    type Error = Error<UartErrorFlags>;
+}
+
+impl<M: Mode> embedded_io::ErrorType for UartRx<M> {
+    type Error = Error<UartErrorFlags>;
+}
+
+
+impl<M: Mode> embedded_io::Write for Uart<M> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        embedded_io::Write::write(&mut self.tx, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        embedded_io::Write::flush(&mut self.tx)
+    }
+}
+
+impl<M: Mode> embedded_io::Write for UartTx<M> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.blocking_write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<M: Mode> core::fmt::Write for Uart<M> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.tx.write_str(s)
+    }
+}
+
+impl<M: Mode> core::fmt::Write for UartTx<M> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.blocking_write(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+
+impl<M: Mode> embedded_io::Read for Uart<M> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io::Read::read(&mut self.rx, buf)
+    }
+}
+
+impl<M: Mode> embedded_io::Read for UartRx<M> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.blocking_read(buf)?;
+        Ok(buf.len())
+    }
+}
+
+
+impl embedded_io_async::Write for Uart<Async> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        embedded_io_async::Write::write(&mut self.tx, buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        embedded_io_async::Write::flush(&mut self.tx).await
+    }
+}
+
+impl embedded_io_async::Write for UartTx<Async> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        UartTx::write(self, buf).await?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // `write` only returns after transmission complete
+        Ok(())
+    }
+}
+
+impl embedded_io_async::Read for Uart<Async> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io_async::Read::read(&mut self.rx, buf).await
+    }
+}
+
+impl embedded_io_async::Read for UartRx<Async> {
+    /// Returns once the line goes idle after at least one byte, see [`UartRx::read_until_idle`].
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_until_idle(buf).await
+    }
+}
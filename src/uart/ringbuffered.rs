@@ -0,0 +1,232 @@
+//! Continuous UART reception into a circular DMA buffer
+
+use core::ffi::c_void;
+use core::marker::PhantomData;
+
+use super::*;
+
+/// UART receiver running circular DMA into a user buffer.
+///
+/// Reception never stops between `read` calls, bytes are only lost if the reader falls more
+/// than a whole buffer behind. Readers are woken on half and full transfer and when the line
+/// goes idle, so a read returns as soon as a frame has ended.
+pub struct RingBufferedUartRx<'d> {
+    handle: csdk::UART_HandleTypeDef,
+    dma: &'d mut dma::DmaChannel,
+    ring: *mut u8,
+    len: usize,
+    read_pos: usize,
+    read_laps: u32,
+    _phantom: PhantomData<&'d mut [u8]>,
+}
+
+// DMA callbacks, `Parent` points at the `State` of the instance so the driver may be moved.
+
+unsafe extern "C" fn ring_half_complete(hdma: *mut csdk::DMA_HandleTypeDef) {
+    let state = &*((*hdma).Parent as *const State);
+    state.rx_waker.wake();
+}
+
+unsafe extern "C" fn ring_complete(hdma: *mut csdk::DMA_HandleTypeDef) {
+    let state = &*((*hdma).Parent as *const State);
+    state.add_lap();
+    state.rx_waker.wake();
+}
+
+unsafe extern "C" fn ring_error(hdma: *mut csdk::DMA_HandleTypeDef) {
+    let state = &*((*hdma).Parent as *const State);
+    state.add_errors(csdk::HAL_UART_ERROR_DMA);
+    state.rx_waker.wake();
+}
+
+impl UartRx<Async> {
+    /// Turn the receiver into one running circular DMA into `buffer`.
+    ///
+    /// `dma` is a peripheral-to-memory channel, it is mapped to the RX request of this USART.
+    /// `buffer` must hold 1 to `u16::MAX` bytes, otherwise `InputError::InvalidBufferLength`
+    /// is returned.
+    pub fn into_ring_buffered<'d>(
+        self,
+        dma: &'d mut dma::DmaChannel,
+        buffer: &'d mut [u8],
+    ) -> Result<RingBufferedUartRx<'d>, Error<UartErrorFlags>> {
+        if buffer.is_empty() || buffer.len() > u16::MAX as usize {
+            return Err(Error::UserInput(InputError::InvalidBufferLength));
+        }
+
+        let mut this = RingBufferedUartRx {
+            handle: self.handle,
+            dma,
+            ring: buffer.as_mut_ptr(),
+            len: buffer.len(),
+            read_pos: 0,
+            read_laps: 0,
+            _phantom: PhantomData,
+        };
+        this.start()?;
+        Ok(this)
+    }
+}
+
+impl<'d> RingBufferedUartRx<'d> {
+    fn start(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        let dma_error = |_| Error::HalError(UartErrorFlags::DMA_ERROR);
+        self.dma.set_request(super::dma_transfer::dma_request(self.handle.Instance, false));
+        self.dma.set_byte_buffer_mode().map_err(dma_error)?;
+        self.dma.set_mode(csdk::DMA_CIRCULAR).map_err(dma_error)?;
+
+        let instance = self.handle.Instance;
+        let state = &STATES[state_index(instance)];
+        state.laps.store(0, Ordering::Relaxed);
+        state.errors.store(0, Ordering::Relaxed);
+        self.read_pos = 0;
+        self.read_laps = 0;
+
+        let hdma = &mut self.dma.handle;
+        hdma.Parent = state as *const State as *mut c_void;
+        hdma.XferHalfCpltCallback = Some(ring_half_complete);
+        hdma.XferCpltCallback = Some(ring_complete);
+        hdma.XferErrorCallback = Some(ring_error);
+        self.dma.register();
+
+        unsafe {
+            dma::enable_irq(&mut self.dma.handle);
+            check(csdk::HAL_DMA_Start_IT(
+                &mut self.dma.handle,
+                &(*instance).DR as *const _ as u32,
+                self.ring as u32,
+                self.len as u32,
+            ), ||Error::HalError(UartErrorFlags::DMA_ERROR))?;
+
+            // drop stale errors, then hand the data register to the DMA
+            let _ = core::ptr::read_volatile(&(*instance).SR);
+            let _ = core::ptr::read_volatile(&(*instance).DR);
+            (*instance).CR3 |= csdk::USART_CR3_DMAR;
+            (*instance).CR1 |= csdk::USART_CR1_IDLEIE;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        let instance = self.handle.Instance;
+        unsafe {
+            (*instance).CR1 &= !csdk::USART_CR1_IDLEIE;
+            (*instance).CR3 &= !csdk::USART_CR3_DMAR;
+            csdk::HAL_DMA_Abort(&mut self.dma.handle);
+        }
+    }
+
+    /// Laps and position of the DMA writer.
+    fn dma_position(&self) -> (u32, usize) {
+        let state = &STATES[state_index(self.handle.Instance)];
+        let tc_flag = 1 << (dma::channel_index(self.dma.handle.Instance) * 4 + 1);
+        critical_section::with(|_| loop {
+            // a wrap whose interrupt has not run yet shows up as a pending TC flag
+            let tc_before = unsafe { core::ptr::read_volatile(&(*csdk::DMA1).ISR) } & tc_flag != 0;
+            let remaining = unsafe { core::ptr::read_volatile(&(*self.dma.handle.Instance).CNDTR) } as usize;
+            let tc_after = unsafe { core::ptr::read_volatile(&(*csdk::DMA1).ISR) } & tc_flag != 0;
+            if tc_before == tc_after {
+                let laps = state.laps.load(Ordering::Relaxed).wrapping_add(tc_after as u32);
+                return (laps, self.len - remaining);
+            }
+        })
+    }
+
+    /// Bytes written by the DMA but not read yet, `None` if the DMA overwrote unread data.
+    fn available(&self) -> Option<usize> {
+        let (laps, pos) = self.dma_position();
+        let available = match laps.wrapping_sub(self.read_laps) {
+            0 => pos.checked_sub(self.read_pos)?,
+            1 => self.len - self.read_pos + pos,
+            _ => return None,
+        };
+        if available > self.len {
+            return None;
+        }
+        Some(available)
+    }
+
+    fn check_errors(&mut self) -> Result<(), Error<UartErrorFlags>> {
+        let instance = self.handle.Instance;
+        let state = &STATES[state_index(instance)];
+        let sr = unsafe { core::ptr::read_volatile(&(*instance).SR) };
+        let mut flags = UartErrorFlags::from_sr(sr);
+        if !flags.is_empty() {
+            // cleared by reading SR then DR
+            let _ = unsafe { core::ptr::read_volatile(&(*instance).DR) };
+        }
+        flags |= UartErrorFlags::from_bits_truncate(state.take_errors());
+        if flags.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::HalError(flags))
+        }
+    }
+
+    /// Skip everything received so far.
+    fn resync(&mut self) {
+        let (laps, pos) = self.dma_position();
+        self.read_laps = laps;
+        self.read_pos = pos;
+    }
+
+    /// Read the bytes received so far, waiting for at least one.
+    ///
+    /// On an overrun (of the USART or of the ring buffer) the unread data is dropped and
+    /// `UartErrorFlags::OVERRUN_ERROR` is returned, the next read continues with fresh data.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<UartErrorFlags>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let index = state_index(self.handle.Instance);
+
+        let available = poll_fn(|cx| {
+            STATES[index].rx_waker.register(cx.waker());
+            if let Err(e) = self.check_errors() {
+                self.resync();
+                return Poll::Ready(Err(e));
+            }
+            match self.available() {
+                None => {
+                    self.resync();
+                    Poll::Ready(Err(Error::HalError(UartErrorFlags::OVERRUN_ERROR)))
+                }
+                Some(0) => Poll::Pending,
+                Some(n) => Poll::Ready(Ok(n)),
+            }
+        }).await?;
+
+        let n = available.min(buf.len());
+        for byte in buf[..n].iter_mut() {
+            *byte = unsafe { core::ptr::read_volatile(self.ring.add(self.read_pos)) };
+            self.read_pos += 1;
+            if self.read_pos == self.len {
+                self.read_pos = 0;
+                self.read_laps = self.read_laps.wrapping_add(1);
+            }
+        }
+
+        // the DMA may have overwritten the bytes while they were copied
+        if self.available().is_none() {
+            self.resync();
+            return Err(Error::HalError(UartErrorFlags::OVERRUN_ERROR));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for RingBufferedUartRx<'_> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl embedded_io::ErrorType for RingBufferedUartRx<'_> {
+    type Error = Error<UartErrorFlags>;
+}
+
+impl embedded_io_async::Read for RingBufferedUartRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        RingBufferedUartRx::read(self, buf).await
+    }
+}